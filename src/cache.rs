@@ -0,0 +1,277 @@
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Time within which records of the same name and type are merged instead of replaced,
+/// see RFC 6762 section 10.2.
+const MULTI_PACKET_WINDOW: Duration = Duration::from_secs(1);
+/// Maximum number of record sets kept, the oldest are evicted first.
+const MAX_RRSETS: usize = 1024;
+
+/// Records received in earlier packets, used to complete announcements split across packets.
+///
+/// Other mDNS implementations may send the SRV record of an instance and the addresses of its
+/// host separately, e.g. in answer to separate queries. Records are kept for their TTL.
+#[derive(Default)]
+pub struct RecordCache {
+    rrsets: BTreeMap<(Name, RecordType), RRSet>,
+    /// the instances whose SRV records point to each host
+    instances: BTreeMap<Name, BTreeSet<Name>>,
+}
+
+struct RRSet {
+    received: Instant,
+    source: IpAddr,
+    records: Vec<Record>,
+}
+
+impl RRSet {
+    fn is_expired(&self, now: Instant) -> bool {
+        let age = now.saturating_duration_since(self.received).as_secs();
+        self.records
+            .iter()
+            .all(|record| u64::from(record.ttl) <= age)
+    }
+}
+
+impl RecordCache {
+    /// Merge the records of a packet into the cache and return all records needed to interpret it.
+    ///
+    /// These are the records of the packet plus the cached SRV, TXT and address records of the
+    /// instances it mentions, directly or via their host. The records of a packet replace all
+    /// cached records of the same name and type, like the cache-flush bit of RFC 6762 section
+    /// 10.2 would, so that removed ports and addresses do not linger. Records received from the
+    /// same source within the last second are kept though, to reassemble announcements split
+    /// across packets. Records with a TTL of zero are returned but not cached.
+    pub fn merge<'a>(
+        &mut self,
+        records: impl Iterator<Item = &'a Record>,
+        source: IpAddr,
+    ) -> Vec<Record> {
+        let now = Instant::now();
+
+        let mut fresh = BTreeMap::<(Name, RecordType), Vec<Record>>::new();
+        for record in records {
+            fresh
+                .entry((record.name.clone(), record.record_type()))
+                .or_default()
+                .push(record.clone());
+        }
+
+        // records from the same source within the last second belong to the same announcement
+        for (key, records) in &mut fresh {
+            let Some(rrset) = self.rrsets.get(key) else {
+                continue;
+            };
+            if rrset.source != source
+                || now.saturating_duration_since(rrset.received) >= MULTI_PACKET_WINDOW
+            {
+                continue;
+            }
+            let mut earlier = Vec::new();
+            self.get(key, now, &mut earlier);
+            earlier.retain(|record| !records.iter().any(|r| r.data == record.data));
+            records.extend(earlier);
+        }
+
+        let mut instances = BTreeSet::new();
+        for (name, record_type) in fresh.keys() {
+            match record_type {
+                RecordType::SRV | RecordType::TXT => {
+                    instances.insert(name.clone());
+                }
+                _ => instances.extend(self.instances.get(name).into_iter().flatten().cloned()),
+            }
+        }
+
+        let mut ret = fresh.values().flatten().cloned().collect::<Vec<_>>();
+        let mut hosts = BTreeSet::new();
+        for instance in instances {
+            for record_type in [RecordType::SRV, RecordType::TXT] {
+                let key = (instance.clone(), record_type);
+                let start = ret.len();
+                if !fresh.contains_key(&key) {
+                    self.get(&key, now, &mut ret);
+                }
+                let srvs = fresh.get(&key).map(|r| &r[..]).unwrap_or(&ret[start..]);
+                for record in srvs {
+                    if let RData::SRV(srv) = &record.data {
+                        hosts.insert(srv.target.clone());
+                    }
+                }
+            }
+        }
+        for host in hosts {
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                let key = (host.clone(), record_type);
+                if !fresh.contains_key(&key) {
+                    self.get(&key, now, &mut ret);
+                }
+            }
+        }
+
+        for (key, mut records) in fresh {
+            self.remove(&key);
+            records.retain(|record| record.ttl > 0);
+            if records.is_empty() {
+                continue;
+            }
+            for record in &records {
+                if let RData::SRV(srv) = &record.data {
+                    self.instances
+                        .entry(srv.target.clone())
+                        .or_default()
+                        .insert(key.0.clone());
+                }
+            }
+            self.rrsets.insert(
+                key,
+                RRSet {
+                    received: now,
+                    source,
+                    records,
+                },
+            );
+        }
+        if self.rrsets.len() > MAX_RRSETS {
+            self.evict(now);
+        }
+
+        ret
+    }
+
+    /// Append the unexpired records of a record set with their remaining TTL.
+    fn get(&self, key: &(Name, RecordType), now: Instant, out: &mut Vec<Record>) {
+        let Some(rrset) = self.rrsets.get(key) else {
+            return;
+        };
+        let age = now.saturating_duration_since(rrset.received).as_secs();
+        out.extend(rrset.records.iter().filter_map(|record| {
+            let ttl = u64::from(record.ttl)
+                .checked_sub(age)
+                .filter(|ttl| *ttl > 0)?;
+            let mut record = record.clone();
+            record.ttl = ttl as u32;
+            Some(record)
+        }));
+    }
+
+    /// Whether a cached SRV record points to the given host.
+    pub fn is_target(&self, host: &Name) -> bool {
+        self.instances.contains_key(host)
+    }
+
+    fn remove(&mut self, key: &(Name, RecordType)) {
+        let Some(rrset) = self.rrsets.remove(key) else {
+            return;
+        };
+        for record in rrset.records {
+            let RData::SRV(srv) = record.data else {
+                continue;
+            };
+            if let Some(instances) = self.instances.get_mut(&srv.target) {
+                instances.remove(&key.0);
+                if instances.is_empty() {
+                    self.instances.remove(&srv.target);
+                }
+            }
+        }
+    }
+
+    /// Drop expired record sets, then the oldest ones until the cache is a quarter below its bound.
+    fn evict(&mut self, now: Instant) {
+        let expired = self
+            .rrsets
+            .iter()
+            .filter(|(_, rrset)| rrset.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.remove(&key);
+        }
+        let target = MAX_RRSETS * 3 / 4;
+        if self.rrsets.len() <= target {
+            return;
+        }
+        let mut by_age = self
+            .rrsets
+            .iter()
+            .map(|(key, rrset)| (rrset.received, key.clone()))
+            .collect::<Vec<_>>();
+        by_age.sort_unstable();
+        let excess = self.rrsets.len() - target;
+        for (_, key) in by_age.into_iter().take(excess) {
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, SRV};
+    use std::{net::Ipv4Addr, str::FromStr};
+
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn srv(port: u16, ttl: u32) -> Record {
+        Record::from_rdata(
+            name("peer._swarm._udp.local."),
+            ttl,
+            RData::SRV(SRV::new(0, 0, port, name("peer-1234.local."))),
+        )
+    }
+
+    fn a(last: u8) -> Record {
+        Record::from_rdata(
+            name("peer-1234.local."),
+            120,
+            RData::A(A::new(10, 0, 0, last)),
+        )
+    }
+
+    #[test]
+    fn split_announcements_are_reassembled() {
+        let mut cache = RecordCache::default();
+        assert_eq!(
+            cache.merge([srv(1234, 120)].iter(), SOURCE),
+            [srv(1234, 120)]
+        );
+        assert!(cache.is_target(&name("peer-1234.local.")));
+
+        // the addresses of a cached instance come with its SRV record
+        let records = cache.merge([a(1)].iter(), SOURCE);
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(&srv(1234, 120)) && records.contains(&a(1)));
+
+        // further addresses from the same source within the window add to the earlier ones
+        let records = cache.merge([a(2)].iter(), SOURCE);
+        assert!(records.contains(&a(1)) && records.contains(&a(2)));
+    }
+
+    #[test]
+    fn records_of_other_sources_replace_cached_ones() {
+        let mut cache = RecordCache::default();
+        cache.merge([srv(1234, 120), a(1)].iter(), SOURCE);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let records = cache.merge([a(2)].iter(), other);
+        assert!(records.contains(&a(2)) && !records.contains(&a(1)));
+    }
+
+    #[test]
+    fn goodbyes_are_returned_but_not_cached() {
+        let mut cache = RecordCache::default();
+        cache.merge([srv(1234, 120)].iter(), SOURCE);
+        assert_eq!(cache.merge([srv(1234, 0)].iter(), SOURCE), [srv(1234, 0)]);
+        assert!(!cache.is_target(&name("peer-1234.local.")));
+        assert_eq!(cache.merge([a(1)].iter(), SOURCE), [a(1)]);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{names, sender::MdnsMsg, updater, Discoverer, Peer};
+use acto::ActoRef;
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{DNSClass, Name, RecordType},
+};
+use rand::{rngs::StdRng, RngExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Number of probes sent before announcing, see RFC 6762 section 8.1.
+const PROBE_COUNT: u8 = 3;
+/// Time between probes, also the maximum initial delay.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How to react to a probe for the local peer ID.
+pub enum ProbeOutcome {
+    Ignore,
+    /// Send the response right away so that the prober notices the conflict.
+    Defend,
+    Conflict(Peer),
+}
+
+/// Detection and resolution of conflicts with other instances using the same peer ID.
+pub struct Conflicts {
+    me: ActoRef<MdnsMsg>,
+    updater: ActoRef<updater::Input>,
+    /// the configured peer ID, to which suffixes are appended when renaming
+    original: String,
+    renames: u32,
+    /// addresses announced before and until when copies of those announcements may be around,
+    /// to recognise outdated copies of our own responses
+    announced: BTreeMap<(IpAddr, u16), Instant>,
+    /// foreign address sets that have already been reported
+    reported: BTreeSet<Vec<(IpAddr, u16)>>,
+    /// number of probes yet to be sent, `None` when done
+    probes_left: Option<u8>,
+    /// invalidates the timeouts of previous probing rounds
+    generation: usize,
+}
+
+impl Conflicts {
+    pub fn new(
+        me: ActoRef<MdnsMsg>,
+        updater: ActoRef<updater::Input>,
+        discoverer: &Discoverer,
+    ) -> Self {
+        Self {
+            me,
+            updater,
+            original: discoverer.peer_id.clone(),
+            renames: 0,
+            announced: BTreeMap::new(),
+            reported: BTreeSet::new(),
+            probes_left: None,
+            generation: 0,
+        }
+    }
+
+    /// Whether the peer ID has not been confirmed yet, in which case it must not be announced.
+    pub fn is_probing(&self) -> bool {
+        self.probes_left.is_some()
+    }
+
+    /// Start probing for the current peer ID after a random delay.
+    pub fn start_probing(&mut self, rng: &mut StdRng) {
+        self.probes_left = Some(PROBE_COUNT);
+        self.generation += 1;
+        let delay = PROBE_INTERVAL / 1_000_000 * rng.random_range(0..1_000_000);
+        self.schedule(delay);
+    }
+
+    fn schedule(&self, delay: Duration) {
+        let me = self.me.clone();
+        let generation = self.generation;
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            me.send(MdnsMsg::ProbeTimeout(generation));
+        });
+    }
+
+    /// Advance the probing on timeout, returning the probe to send if any.
+    pub fn next_probe(
+        &mut self,
+        generation: usize,
+        response: &Option<Message>,
+        discoverer: &Discoverer,
+        service_name: &Name,
+    ) -> Option<Message> {
+        if generation != self.generation {
+            return None;
+        }
+        match self.probes_left? {
+            0 => {
+                tracing::debug!("no conflict found for peer ID {}", discoverer.peer_id);
+                self.probes_left = None;
+                None
+            }
+            n => {
+                self.probes_left = Some(n - 1);
+                self.schedule(PROBE_INTERVAL);
+                make_probe(response.as_ref()?, discoverer, service_name)
+            }
+        }
+    }
+
+    /// Remember the current local addresses as our own for as long as they were announced for.
+    ///
+    /// This is needed before changing the addresses, the current ones are always our own.
+    pub fn claim(&mut self, discoverer: &Discoverer, ttl: u32) {
+        let now = Instant::now();
+        self.announced.retain(|_, until| *until > now);
+        if let Some(peer) = discoverer.peers.get(&discoverer.peer_id) {
+            let until = now + Duration::from_secs(ttl.into());
+            for addr in &peer.addrs {
+                self.announced.insert(*addr, until);
+            }
+        }
+    }
+
+    fn is_own(&self, peer: &Peer, discoverer: &Discoverer) -> bool {
+        let now = Instant::now();
+        let current = discoverer
+            .peers
+            .get(&discoverer.peer_id)
+            .map(|peer| peer.addrs.as_slice())
+            .unwrap_or_default();
+        peer.addrs.iter().all(|addr| {
+            current.contains(addr) || self.announced.get(addr).is_some_and(|until| *until > now)
+        })
+    }
+
+    /// Remove a foreign announcement of the local peer ID from a response and return it.
+    pub fn check_response(
+        &self,
+        peers: &mut BTreeMap<String, Peer>,
+        discoverer: &Discoverer,
+    ) -> Option<Peer> {
+        if self.is_own(peers.get(&discoverer.peer_id)?, discoverer) {
+            return None;
+        }
+        let foreign = peers.remove(&discoverer.peer_id)?;
+        // the other instance is going away
+        (!foreign.ttl.is_zero()).then_some(foreign)
+    }
+
+    /// Compare a probe by another instance with our claim to the local peer ID.
+    pub fn check_probe(
+        &self,
+        claims: &BTreeMap<String, Peer>,
+        discoverer: &Discoverer,
+    ) -> ProbeOutcome {
+        let Some(claim) = claims.get(&discoverer.peer_id) else {
+            return ProbeOutcome::Ignore;
+        };
+        if self.is_own(claim, discoverer) {
+            return ProbeOutcome::Ignore;
+        }
+        if !self.is_probing() {
+            return ProbeOutcome::Defend;
+        }
+        // simultaneous probes: the lexicographically later data wins, see RFC 6762 section 8.2
+        let own = discoverer
+            .peers
+            .get(&discoverer.peer_id)
+            .map(|peer| peer.addrs.as_slice())
+            .unwrap_or_default();
+        if claim.addrs.as_slice() > own {
+            ProbeOutcome::Conflict(claim.clone())
+        } else {
+            ProbeOutcome::Ignore
+        }
+    }
+
+    /// Report a conflict and rename if configured, returning whether the peer ID was changed.
+    ///
+    /// After renaming, the new peer ID is probed for.
+    pub fn resolve(
+        &mut self,
+        foreign: Peer,
+        discoverer: &mut Discoverer,
+        service_name: &Name,
+        rng: &mut StdRng,
+    ) -> bool {
+        let peer_id = discoverer.peer_id.clone();
+        if self.reported.insert(foreign.addrs.clone()) {
+            tracing::warn!(
+                "peer ID {} is also used by an instance at {:?}",
+                peer_id,
+                foreign.addrs
+            );
+            self.updater
+                .send(updater::Input::Conflict(peer_id.clone(), foreign));
+        }
+        if !discoverer.auto_rename {
+            return false;
+        }
+        #[cfg(feature = "signing")]
+        if discoverer.signing_key.is_some() {
+            return false;
+        }
+        self.renames += 1;
+        let new_peer_id = format!("{}-{}", self.original, self.renames + 1);
+        let valid = names::instance_name(&new_peer_id, service_name).is_ok();
+        if !valid {
+            tracing::warn!(
+                "cannot rename to {}, keeping peer ID {}",
+                new_peer_id,
+                peer_id
+            );
+            return false;
+        }
+        tracing::info!("renaming peer ID {} to {}", peer_id, new_peer_id);
+        let me = discoverer.peers.remove(&peer_id).unwrap_or_else(Peer::new);
+        discoverer.peers.insert(new_peer_id.clone(), me.clone());
+        discoverer.peer_id = new_peer_id.clone();
+        self.reported.clear();
+        self.updater
+            .send(updater::Input::Renamed(peer_id, new_peer_id, me));
+        self.start_probing(rng);
+        true
+    }
+}
+
+/// A query for the local instance name carrying the proposed records in the authority section.
+///
+/// When a probe is split into several packets, only the records in the first one take part in
+/// the tiebreak; conflicts missed this way are still noticed in the responses.
+fn make_probe(response: &Message, discoverer: &Discoverer, service_name: &Name) -> Option<Message> {
+    let instance = names::instance_name(&discoverer.peer_id, service_name).ok()?;
+    let mut msg = Message::new(0, MessageType::Query, OpCode::Query);
+    let mut query = Query::new();
+    query.set_query_class(DNSClass::IN);
+    query.set_query_type(RecordType::ANY);
+    query.set_name(instance);
+    msg.add_query(query);
+    // the unique records of the response, i.e. all but the shared PTR records
+    let records = response.answers.iter().chain(&response.additionals);
+    msg.add_authorities(
+        records
+            .filter(|record| record.record_type() != RecordType::PTR)
+            .cloned(),
+    );
+    Some(msg)
+}
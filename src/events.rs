@@ -0,0 +1,153 @@
+use crate::Peer;
+use futures_core::Stream;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+pub(crate) type EventSender = broadcast::Sender<DiscoveryEvent>;
+
+/// Default number of events buffered for each [`Events`] stream.
+pub(crate) const DEFAULT_EVENT_BUFFER: usize = 256;
+
+/// An observation made by the discovery service about a peer.
+///
+/// Events are passed to the callback registered with [`Discoverer::with_event_callback`]
+/// and yielded by the [`Events`] stream.
+///
+/// [`Discoverer::with_event_callback`]: crate::Discoverer::with_event_callback
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiscoveryEvent {
+    /// A peer was seen for the first time, or for the first time after it expired.
+    Discovered { peer_id: String, peer: Peer },
+    /// A known peer announced different addresses or TXT attributes.
+    ///
+    /// The flags indicate which parts differ from the previously known state, at least one
+    /// of them is set.
+    Updated {
+        peer_id: String,
+        peer: Peer,
+        addrs_changed: bool,
+        txt_changed: bool,
+    },
+    /// A response from a known peer was received that did not change its addresses or TXT
+    /// attributes.
+    ///
+    /// These events are only emitted when enabled with [`Discoverer::with_liveness_events`].
+    ///
+    /// [`Discoverer::with_liveness_events`]: crate::Discoverer::with_liveness_events
+    Refreshed { peer_id: String, peer: Peer },
+    /// A peer has not been seen for too long and was removed.
+    ///
+    /// The contained [`Peer`] holds the last known addresses and TXT attributes.
+    Expired { peer_id: String, peer: Peer },
+    /// Another instance announces the local peer ID with different addresses.
+    ///
+    /// The contained [`Peer`] holds the foreign announcement. Each distinct set of foreign
+    /// addresses is reported once, see also [`Discoverer::with_auto_rename`].
+    ///
+    /// [`Discoverer::with_auto_rename`]: crate::Discoverer::with_auto_rename
+    Conflict { peer_id: String, peer: Peer },
+    /// The local peer ID was changed to resolve a conflict.
+    ///
+    /// `peer_id` is the new peer ID, `previous` the one that was in conflict, and `peer`
+    /// holds the local addresses and TXT attributes.
+    Renamed {
+        peer_id: String,
+        peer: Peer,
+        previous: String,
+    },
+}
+
+impl DiscoveryEvent {
+    /// The ID of the peer this event is about.
+    pub fn peer_id(&self) -> &str {
+        match self {
+            DiscoveryEvent::Discovered { peer_id, .. }
+            | DiscoveryEvent::Updated { peer_id, .. }
+            | DiscoveryEvent::Refreshed { peer_id, .. }
+            | DiscoveryEvent::Expired { peer_id, .. }
+            | DiscoveryEvent::Conflict { peer_id, .. }
+            | DiscoveryEvent::Renamed { peer_id, .. } => peer_id,
+        }
+    }
+
+    /// The state of the peer this event is about.
+    ///
+    /// For [`DiscoveryEvent::Expired`] this is the last known state.
+    pub fn peer(&self) -> &Peer {
+        match self {
+            DiscoveryEvent::Discovered { peer, .. }
+            | DiscoveryEvent::Updated { peer, .. }
+            | DiscoveryEvent::Refreshed { peer, .. }
+            | DiscoveryEvent::Expired { peer, .. }
+            | DiscoveryEvent::Conflict { peer, .. }
+            | DiscoveryEvent::Renamed { peer, .. } => peer,
+        }
+    }
+}
+
+/// Indication that an [`Events`] stream was not polled fast enough.
+///
+/// Each stream buffers a bounded number of events (see [`Discoverer::with_event_buffer`]).
+/// When the buffer is full, the oldest events are dropped and the next poll of the stream
+/// yields this error, after which the stream continues with the oldest retained event.
+///
+/// [`Discoverer::with_event_buffer`]: crate::Discoverer::with_event_buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("event stream lagged behind, {skipped} events were dropped")]
+pub struct Lagged {
+    skipped: u64,
+}
+
+impl Lagged {
+    /// Number of events that were dropped.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+/// Stream of discovery events, obtained from [`DropGuard::events`].
+///
+/// The stream yields the same events that are passed to the callback registered
+/// with [`Discoverer::with_event_callback`]. It ends when the discovery service stops.
+///
+/// [`DropGuard::events`]: crate::DropGuard::events
+/// [`Discoverer::with_event_callback`]: crate::Discoverer::with_event_callback
+pub struct Events {
+    inner: BroadcastStream<DiscoveryEvent>,
+}
+
+impl Events {
+    pub(crate) fn new(sender: &EventSender) -> Self {
+        Self {
+            inner: BroadcastStream::new(sender.subscribe()),
+        }
+    }
+}
+
+impl Stream for Events {
+    type Item = Result<DiscoveryEvent, Lagged>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx).map(|item| {
+            item.map(|res| {
+                res.map_err(|BroadcastStreamRecvError::Lagged(skipped)| Lagged { skipped })
+            })
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl std::fmt::Debug for Events {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Events").finish_non_exhaustive()
+    }
+}
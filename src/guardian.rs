@@ -1,89 +1,156 @@
 use crate::{
-    receiver::{receiver, ReceiverError},
+    events::EventSender,
+    interfaces::{watcher, WatchConfig},
+    receiver::{receiver, Dispatch, ReceiverError, Services},
     sender::{self, sender},
-    socket::Sockets,
-    updater::updater,
-    Discoverer,
+    stats::{Counted, Counters},
+    transport::{Link, Transport},
+    updater::{self, updater},
+    Discoverer, Peer,
 };
-use acto::{AcTokioRuntime, ActoCell, ActoInput, ActoRef};
+use acto::{AcTokioRuntime, ActoCell, ActoId, ActoInput, ActoRef};
 use hickory_proto::rr::Name;
-use std::{collections::HashMap, mem::replace, net::IpAddr};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    mem::replace,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::oneshot;
 
 pub enum Input {
-    RemoveAll,
-    RemovePort(u16),
-    RemoveAddr(IpAddr),
-    AddAddr(u16, Vec<IpAddr>),
-    SetTxt(String, Option<String>),
-    RemoveTxt(String),
+    /// A change to the announcement, the only input passed on to the sender as is.
+    Update(sender::Update),
     AddInterface(IpAddr),
     RemoveInterface(IpAddr),
+    AddInterfaceV6(u32),
+    RemoveInterfaceV6(u32),
+    GetPeers(oneshot::Sender<BTreeMap<String, Peer>>),
+    GetPeer(String, oneshot::Sender<Option<Peer>>),
+    GetAnnouncementSize(oneshot::Sender<usize>),
+    SetCadence(Duration),
+    SetResponseRate(f32),
+    Stop,
+}
+
+/// One of the discovery services sharing the sockets of a guardian.
+pub struct Service {
+    pub discoverer: Discoverer,
+    pub service_name: Name,
+    pub events: EventSender,
+    pub stats: Arc<Counters>,
+}
+
+enum ServiceMsg {
+    Input(Input),
+    Mdns(sender::MdnsMsg),
 }
 
+/// Owns the sockets and receivers shared by the services, inputs are addressed to a
+/// service by its index.
 pub async fn guardian(
-    mut ctx: ActoCell<Input, AcTokioRuntime, Result<(), ReceiverError>>,
-    mut discoverer: Discoverer,
-    sockets: Sockets,
-    service_name: Name,
+    mut ctx: ActoCell<(usize, Input), AcTokioRuntime, Result<(), ReceiverError>>,
+    link: Link,
+    services: Vec<Service>,
 ) {
-    let callback = replace(&mut discoverer.callback, Box::new(|_, _| {}));
-    let tau = discoverer.tau;
-    let phi = discoverer.phi;
-    let upd_ref = ctx.supervise(
-        ctx.spawn("updater", move |ctx| updater(ctx, tau, phi, callback))
-            .map_handle(Ok),
-    );
-
-    let sockets2 = sockets.clone();
-    let sn = service_name.clone();
-    let snd_ref = ctx.supervise(
-        ctx.spawn("sender", move |ctx| {
-            sender(ctx, sockets, upd_ref, discoverer, sn)
-        })
-        .map_handle(Ok),
-    );
+    // interfaces are shared, so they are configured by the first service
+    let watch_config = services
+        .first()
+        .filter(|service| service.discoverer.auto_interfaces)
+        .map(|service| WatchConfig {
+            multicast: true,
+            filter: service.discoverer.interface_filter.clone(),
+            ports: Default::default(),
+            local_addrs: Default::default(),
+        });
 
-    if let Some(v4) = sockets2.v4() {
-        let service_name = service_name.clone();
-        let snd_ref = snd_ref.clone();
-        ctx.spawn_supervised("receiver_v4", move |ctx| {
-            receiver(ctx, service_name, v4, snd_ref)
+    let transport = link.transport();
+    let mut service_refs = BTreeMap::new();
+    let mut dispatch = Vec::new();
+    for (index, service) in services.into_iter().enumerate() {
+        let service_name = service.service_name.clone();
+        #[cfg(feature = "signing")]
+        let verification = service.discoverer.verification;
+        // sender and receivers take the time of packets from the clock of the network
+        #[cfg(feature = "swarm-secret")]
+        let (service, secret) = {
+            let mut service = service;
+            if let Some(secret) = &mut service.discoverer.swarm_secret {
+                secret.set_clock(link.clock());
+            }
+            let secret = service.discoverer.swarm_secret.clone();
+            (service, secret)
+        };
+        let stats = service.stats.clone();
+        let transport = transport.clone();
+        let srv_ref = ctx.supervise(ctx.spawn(&format!("service_{}", index), move |ctx| {
+            run_service(ctx, transport, service)
+        }));
+        dispatch.push(Dispatch {
+            service_name,
+            #[cfg(feature = "signing")]
+            verification,
+            #[cfg(feature = "swarm-secret")]
+            secret,
+            target: srv_ref.contramap(ServiceMsg::Mdns),
+            cache: Default::default(),
+            stats,
         });
+        service_refs.insert(index, srv_ref);
     }
+    let dispatch: Services = dispatch.into();
+    // services stopped by dropping their guard, whose termination is expected
+    let mut stopping = BTreeSet::<ActoId>::new();
 
-    if let Some(v6) = sockets2.v6() {
-        let service_name = service_name.clone();
-        let snd_ref = snd_ref.clone();
-        ctx.spawn_supervised("receiver_v6", move |ctx| {
-            receiver(ctx, service_name, v6, snd_ref)
-        });
+    for (name, socket) in link.receivers() {
+        let dispatch = dispatch.clone();
+        ctx.spawn_supervised(name, move |ctx| receiver(ctx, dispatch, socket));
     }
 
     // Track interface receivers so we can stop them when interfaces are removed
     let mut interface_receivers: HashMap<IpAddr, ActoRef<()>> = HashMap::new();
 
     // Start receivers for initial interface sockets
-    let initial_interfaces = sockets2.get_all_interface_addresses_v4();
+    let sockets = link.sockets();
+    let initial_interfaces = sockets
+        .map(|s| s.get_all_interface_addresses_v4())
+        .unwrap_or_default();
     for addr in initial_interfaces {
-        if let Some(socket) = sockets2.get_interface_socket_v4(addr) {
-            let service_name = service_name.clone();
-            let snd_ref = snd_ref.clone();
+        if let Some(socket) = sockets.and_then(|s| s.get_interface_socket_v4(addr)) {
+            let dispatch = dispatch.clone();
             let addr_str = addr.to_string();
             let receiver_ref = ctx
                 .spawn_supervised(&format!("receiver_interface_{}", addr_str), move |ctx| {
-                    receiver(ctx, service_name, socket, snd_ref)
+                    receiver(ctx, dispatch, socket)
                 });
             interface_receivers.insert(IpAddr::V4(addr), receiver_ref);
             tracing::info!("Started receiver for initial interface {}", addr);
         }
     }
 
+    if let Some(config) = watch_config {
+        let me = ctx.me().contramap(|input| (0, input));
+        ctx.supervise(
+            ctx.spawn("interfaces", move |ctx| watcher(ctx, me, config))
+                .map_handle(Ok),
+        );
+    }
+
     // only stop when a supervised actor stops
     loop {
         let msg = ctx.recv().await;
         match msg {
             ActoInput::NoMoreSenders => {}
             ActoInput::Supervision { id, name, result } => {
+                if stopping.remove(&id) {
+                    tracing::debug!("actor {:?} ({}) stopped on request", id, name);
+                    if service_refs.is_empty() && stopping.is_empty() {
+                        // the last service has said goodbye
+                        break;
+                    }
+                    continue;
+                }
                 match result {
                     Ok(Ok(_)) => tracing::warn!("actor {:?} ({}) stopped", id, name),
                     Ok(Err(e)) => {
@@ -95,38 +162,193 @@ pub async fn guardian(
                 }
                 break;
             }
-            ActoInput::Message(msg) => match &msg {
+            ActoInput::Message((service, msg)) => match msg {
                 Input::AddInterface(addr) => {
+                    if interface_receivers.contains_key(&addr) {
+                        continue;
+                    }
+                    let Some(sockets2) = sockets else {
+                        tracing::debug!("not using sockets, ignoring interface {}", addr);
+                        continue;
+                    };
                     if let IpAddr::V4(ipv4) = addr {
-                        if let Err(e) = sockets2.add_interface_v4(*ipv4) {
+                        if let Err(e) = sockets2.add_interface_v4(ipv4) {
                             tracing::warn!("Failed to add interface {}: {}", addr, e);
                         } else {
                             // Start a receiver for the new interface socket
-                            if let Some(socket) = sockets2.get_interface_socket_v4(*ipv4) {
-                                let service_name = service_name.clone();
-                                let snd_ref = snd_ref.clone();
+                            if let Some(socket) = sockets2.get_interface_socket_v4(ipv4) {
+                                let dispatch = dispatch.clone();
                                 let addr_str = addr.to_string();
                                 let receiver_ref = ctx.spawn_supervised(
                                     &format!("receiver_interface_{}", addr_str),
-                                    move |ctx| receiver(ctx, service_name, socket, snd_ref),
+                                    move |ctx| receiver(ctx, dispatch, socket),
                                 );
-                                interface_receivers.insert(*addr, receiver_ref);
+                                interface_receivers.insert(addr, receiver_ref);
                                 tracing::info!("Started receiver for interface {}", addr);
                             }
                         }
                     }
                 }
                 Input::RemoveInterface(addr) => {
-                    if let IpAddr::V4(ipv4) = addr {
-                        sockets2.remove_interface_v4(*ipv4);
+                    if let (IpAddr::V4(ipv4), Some(sockets2)) = (addr, sockets) {
+                        sockets2.remove_interface_v4(ipv4);
                         // Remove the receiver reference for this interface
-                        if interface_receivers.remove(addr).is_some() {
+                        if interface_receivers.remove(&addr).is_some() {
                             tracing::info!("Removed receiver reference for interface {}", addr);
                         }
                     }
                 }
-                _ => {
-                    snd_ref.send(sender::MdnsMsg::Update(msg));
+                Input::AddInterfaceV6(index) => {
+                    // reception is handled by the existing IPv6 receiver
+                    if let Some(Err(e)) = sockets.map(|s| s.add_interface_v6(index)) {
+                        tracing::warn!("Failed to add interface {}: {}", index, e);
+                    }
+                }
+                Input::RemoveInterfaceV6(index) => {
+                    if let Some(sockets2) = sockets {
+                        sockets2.remove_interface_v6(index);
+                    }
+                }
+                Input::Stop => {
+                    if let Some(srv_ref) = service_refs.remove(&service) {
+                        stopping.insert(srv_ref.id());
+                        srv_ref.send(ServiceMsg::Input(Input::Stop));
+                    }
+                }
+                msg => {
+                    if let Some(srv_ref) = service_refs.get(&service) {
+                        srv_ref.send(ServiceMsg::Input(msg));
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Runs updater and sender of a single service until it is stopped.
+async fn run_service(
+    mut ctx: ActoCell<ServiceMsg, AcTokioRuntime, Result<(), ReceiverError>>,
+    transport: Arc<dyn Transport>,
+    service: Service,
+) -> Result<(), ReceiverError> {
+    let Service {
+        mut discoverer,
+        service_name,
+        events,
+        stats,
+    } = service;
+    let callback = replace(&mut discoverer.callback, Box::new(|_| {}));
+    let tau = discoverer.tau;
+    let phi = discoverer.phi;
+    let liveness_events = discoverer.liveness_events;
+    let peer_filter = discoverer.peer_filter.clone();
+    #[cfg(feature = "serde")]
+    let peer_cache = discoverer.peer_cache.clone();
+    #[cfg(not(feature = "serde"))]
+    let peer_cache = None;
+    let watch_config = (!discoverer.auto_ports.is_empty()).then(|| WatchConfig {
+        multicast: false,
+        filter: discoverer.interface_filter.clone(),
+        ports: discoverer.auto_ports.clone(),
+        local_addrs: discoverer.local_addr_filter,
+    });
+    let upd_ref = ctx.supervise(
+        ctx.spawn("updater", move |ctx| {
+            updater(
+                ctx,
+                tau,
+                phi,
+                callback,
+                peer_filter,
+                events,
+                liveness_events,
+                peer_cache,
+            )
+        })
+        .map_handle(Ok),
+    );
+
+    let upd_ref2 = upd_ref.clone();
+    let transport = Arc::new(Counted {
+        transport,
+        counters: stats.clone(),
+    });
+    let snd_ref = ctx.supervise(
+        ctx.spawn("sender", move |ctx| {
+            sender(ctx, transport, upd_ref2, discoverer, service_name, stats)
+        })
+        .map_handle(Ok),
+    );
+
+    if let Some(config) = watch_config {
+        let me = ctx.me().contramap(ServiceMsg::Input);
+        ctx.supervise(
+            ctx.spawn("interfaces", move |ctx| watcher(ctx, me, config))
+                .map_handle(Ok),
+        );
+    }
+
+    let mut stopping = false;
+    loop {
+        match ctx.recv().await {
+            ActoInput::NoMoreSenders => {}
+            ActoInput::Supervision { id, .. } if stopping && id == snd_ref.id() => return Ok(()),
+            ActoInput::Supervision { id, name, result } => {
+                // the guardian reports the failure of the whole service
+                return match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        tracing::warn!("actor {:?} ({}) aborted: {}", id, name, e);
+                        Ok(())
+                    }
+                };
+            }
+            ActoInput::Message(ServiceMsg::Mdns(msg)) => {
+                snd_ref.send(msg);
+            }
+            ActoInput::Message(ServiceMsg::Input(msg)) => match msg {
+                Input::Stop => {
+                    // the sender stops after its goodbye announcement, which must not wait for
+                    // the peer table to be saved
+                    let sending = snd_ref.send(sender::MdnsMsg::Stop);
+                    // keep the final peer table meanwhile
+                    #[cfg(feature = "serde")]
+                    {
+                        let (tx, rx) = oneshot::channel();
+                        if upd_ref.send(updater::Input::Save(tx)) {
+                            let _ = rx.await;
+                        }
+                    }
+                    if !sending {
+                        return Ok(());
+                    }
+                    stopping = true;
+                }
+                Input::GetPeers(reply) => {
+                    upd_ref.send(updater::Input::GetPeers(reply));
+                }
+                Input::GetPeer(peer_id, reply) => {
+                    upd_ref.send(updater::Input::GetPeer(peer_id, reply));
+                }
+                Input::GetAnnouncementSize(reply) => {
+                    snd_ref.send(sender::MdnsMsg::GetSize(reply));
+                }
+                Input::SetCadence(tau) => {
+                    upd_ref.send(updater::Input::SetCadence(tau));
+                    snd_ref.send(sender::MdnsMsg::SetCadence(tau));
+                }
+                Input::SetResponseRate(phi) => {
+                    upd_ref.send(updater::Input::SetResponseRate(phi));
+                    snd_ref.send(sender::MdnsMsg::SetResponseRate(phi));
+                }
+                Input::Update(update) => {
+                    snd_ref.send(sender::MdnsMsg::Update(update));
+                }
+                Input::AddInterface(_)
+                | Input::RemoveInterface(_)
+                | Input::AddInterfaceV6(_)
+                | Input::RemoveInterfaceV6(_) => {
+                    // shared sockets are managed by the guardian
                 }
             },
         }
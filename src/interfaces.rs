@@ -0,0 +1,321 @@
+use crate::{guardian, sender::Update};
+use acto::{ActoCell, ActoRef, ActoRuntime};
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::sleep;
+
+pub(crate) type InterfaceFilter = Arc<dyn Fn(&Interface) -> bool + Send + Sync + 'static>;
+
+/// Time to let a burst of change notifications settle before rescanning.
+const SETTLE_DELAY: Duration = Duration::from_millis(200);
+/// Rescan interval when change notifications are not available.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Known name prefixes of virtual interfaces, used where the kernel does not tell.
+#[cfg(not(target_os = "linux"))]
+const VIRTUAL_PREFIXES: &[&str] = &[
+    "docker",
+    "br-",
+    "veth",
+    "virbr",
+    "vmnet",
+    "vboxnet",
+    "utun",
+    "bridge",
+    "vEthernet",
+];
+
+/// Configuration of the interface watcher.
+pub(crate) struct WatchConfig {
+    /// Whether to keep the multicast interfaces in sync.
+    pub multicast: bool,
+    pub filter: InterfaceFilter,
+    /// Ports to announce on all local addresses selected by `local_addrs`.
+    pub ports: BTreeSet<u16>,
+    pub local_addrs: LocalAddrFilter,
+}
+
+/// Selects which local addresses are announced by [`Discoverer::with_port_on_all_interfaces`].
+///
+/// By default, loopback and link-local addresses as well as addresses of virtual interfaces
+/// (e.g. container bridges or VPN tunnels) are excluded. Use the methods to include them.
+///
+/// [`Discoverer::with_port_on_all_interfaces`]: crate::Discoverer::with_port_on_all_interfaces
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalAddrFilter {
+    loopback: bool,
+    link_local: bool,
+    virtual_interfaces: bool,
+}
+
+impl LocalAddrFilter {
+    /// Include loopback addresses like `127.0.0.1` and `::1`.
+    pub fn with_loopback(mut self, include: bool) -> Self {
+        self.loopback = include;
+        self
+    }
+
+    /// Include link-local addresses, i.e. `169.254.0.0/16` and `fe80::/10`.
+    pub fn with_link_local(mut self, include: bool) -> Self {
+        self.link_local = include;
+        self
+    }
+
+    /// Include addresses of virtual interfaces (see [`Interface::is_virtual`]).
+    pub fn with_virtual_interfaces(mut self, include: bool) -> Self {
+        self.virtual_interfaces = include;
+        self
+    }
+
+    fn accepts(&self, iface: &Interface) -> bool {
+        (self.loopback || !iface.is_loopback())
+            && (self.link_local || !iface.is_link_local())
+            // loopback interfaces are virtual as well, but are governed by the first flag
+            && (self.virtual_interfaces || iface.is_loopback() || !iface.is_virtual())
+    }
+}
+
+/// An address of a local network interface.
+///
+/// This is passed to the filter registered with [`Discoverer::with_interface_filter`].
+///
+/// [`Discoverer::with_interface_filter`]: crate::Discoverer::with_interface_filter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    name: String,
+    index: Option<u32>,
+    addr: IpAddr,
+    is_virtual: bool,
+}
+
+impl Interface {
+    /// The name of the interface, e.g. `eth0` or `docker0`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The index of the interface, if known.
+    pub fn index(&self) -> Option<u32> {
+        self.index
+    }
+
+    /// The address assigned to the interface.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns true if this is a loopback address.
+    pub fn is_loopback(&self) -> bool {
+        self.addr.is_loopback()
+    }
+
+    /// Returns true if this is a link-local address.
+    pub fn is_link_local(&self) -> bool {
+        match self.addr {
+            IpAddr::V4(addr) => addr.is_link_local(),
+            IpAddr::V6(addr) => (addr.segments()[0] & 0xffc0) == 0xfe80,
+        }
+    }
+
+    /// Returns true if the interface is not backed by a hardware device.
+    ///
+    /// On Linux this is what the kernel reports, which includes loopback, bridges, veth pairs and
+    /// tunnels. On other platforms the interface name is compared against well-known prefixes
+    /// like `docker`, `veth` or `utun`.
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+}
+
+fn is_virtual(name: &str) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        std::path::Path::new("/sys/devices/virtual/net")
+            .join(name)
+            .exists()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        VIRTUAL_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    }
+}
+
+/// List the addresses of all local network interfaces.
+pub(crate) fn scan() -> Vec<Interface> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .map(|iface| Interface {
+                addr: iface.ip(),
+                is_virtual: is_virtual(&iface.name),
+                name: iface.name,
+                index: iface.index,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!("cannot list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Keeps the multicast interfaces and announced addresses of the guardian in sync with the
+/// local network interfaces.
+pub async fn watcher(
+    _ctx: ActoCell<(), impl ActoRuntime>,
+    guardian: ActoRef<guardian::Input>,
+    config: WatchConfig,
+) {
+    let mut changes = Changes::new();
+    let mut v4 = BTreeSet::<Ipv4Addr>::new();
+    let mut v6 = BTreeSet::<u32>::new();
+    let mut announced = BTreeSet::<IpAddr>::new();
+
+    loop {
+        let all = scan()
+            .into_iter()
+            .filter(|iface| (config.filter)(iface))
+            .collect::<Vec<_>>();
+
+        if !config.ports.is_empty() {
+            let current = all
+                .iter()
+                .filter(|iface| config.local_addrs.accepts(iface))
+                .map(|iface| iface.addr)
+                .collect::<BTreeSet<_>>();
+            let added = current.difference(&announced).copied().collect::<Vec<_>>();
+            let removed = announced.difference(&current).copied().collect::<Vec<_>>();
+            for port in &config.ports {
+                if !added.is_empty() {
+                    tracing::debug!(?added, "announcing new local addresses");
+                    guardian.send(guardian::Input::Update(Update::AddAddr(
+                        *port,
+                        added.clone(),
+                    )));
+                }
+                if !removed.is_empty() {
+                    tracing::debug!(?removed, "no longer announcing local addresses");
+                    guardian.send(guardian::Input::Update(Update::RemoveAddrs(
+                        *port,
+                        removed.clone(),
+                    )));
+                }
+            }
+            announced = current;
+        }
+
+        if !config.multicast {
+            changes.next().await;
+            continue;
+        }
+
+        let interfaces = all
+            .into_iter()
+            .filter(|iface| !iface.is_loopback())
+            .collect::<Vec<_>>();
+
+        let current_v4 = interfaces
+            .iter()
+            .filter_map(|iface| match iface.addr {
+                IpAddr::V4(addr) => Some(addr),
+                IpAddr::V6(_) => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let current_v6 = interfaces
+            .iter()
+            .filter(|iface| iface.addr.is_ipv6())
+            .filter_map(|iface| iface.index)
+            .collect::<BTreeSet<_>>();
+
+        for addr in current_v4.difference(&v4) {
+            tracing::debug!("interface {} appeared", addr);
+            guardian.send(guardian::Input::AddInterface(IpAddr::V4(*addr)));
+        }
+        for addr in v4.difference(&current_v4) {
+            tracing::debug!("interface {} vanished", addr);
+            guardian.send(guardian::Input::RemoveInterface(IpAddr::V4(*addr)));
+        }
+        for index in current_v6.difference(&v6) {
+            tracing::debug!("interface index {} appeared", index);
+            guardian.send(guardian::Input::AddInterfaceV6(*index));
+        }
+        for index in v6.difference(&current_v6) {
+            tracing::debug!("interface index {} vanished", index);
+            guardian.send(guardian::Input::RemoveInterfaceV6(*index));
+        }
+        v4 = current_v4;
+        v6 = current_v6;
+
+        changes.next().await;
+    }
+}
+
+/// Source of notifications about possibly changed network interfaces.
+struct Changes {
+    #[cfg(target_os = "linux")]
+    netlink: Option<netlink_sys::TokioSocket>,
+}
+
+impl Changes {
+    fn new() -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            netlink: netlink::subscribe()
+                .inspect_err(|e| {
+                    tracing::warn!("cannot subscribe to netlink, polling interfaces: {}", e)
+                })
+                .ok(),
+        }
+    }
+
+    /// Wait until the network interfaces may have changed.
+    async fn next(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(socket) = &self.netlink {
+            use netlink_sys::AsyncSocketExt;
+
+            match socket.recv_from_full().await {
+                Ok(_) => {
+                    sleep(SETTLE_DELAY).await;
+                    // drain the notifications received in the meantime
+                    while let Ok(Ok(_)) =
+                        tokio::time::timeout(Duration::ZERO, socket.recv_from_full()).await
+                    {
+                    }
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!("cannot receive from netlink, polling interfaces: {}", e);
+                    self.netlink = None;
+                }
+            }
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use netlink_sys::{protocols::NETLINK_ROUTE, AsyncSocket, SocketAddr, TokioSocket};
+
+    // multicast groups from linux/rtnetlink.h
+    const RTMGRP_LINK: u32 = 0x1;
+    const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+    const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+    /// Open a netlink socket receiving link and address change notifications.
+    pub fn subscribe() -> std::io::Result<TokioSocket> {
+        let mut socket = TokioSocket::new(NETLINK_ROUTE)?;
+        socket.socket_mut().bind(&SocketAddr::new(
+            0,
+            RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR,
+        ))?;
+        Ok(socket)
+    }
+}
@@ -1,30 +1,73 @@
 #![doc = include_str!("../README.md")]
 
+mod cache;
+mod conflict;
+mod events;
 mod guardian;
+mod interfaces;
+mod names;
+mod packets;
+#[cfg(feature = "serde")]
+mod persist;
 mod receiver;
+#[cfg(feature = "swarm-secret")]
+mod secret;
 mod sender;
+#[cfg(feature = "signing")]
+mod signing;
 mod socket;
+mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod transport;
 mod updater;
 
 use acto::{AcTokio, ActoHandle, ActoRef, ActoRuntime, SupervisionRef, TokioJoinHandle};
+use events::EventSender;
 use hickory_proto::rr::Name;
+use interfaces::InterfaceFilter;
+use sender::Update;
 use socket::{SocketError, Sockets};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt::Display,
     net::{IpAddr, Ipv4Addr},
+    pin::Pin,
     str::FromStr,
-    time::{Duration, Instant},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 use thiserror::Error;
-use tokio::runtime::Handle;
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, oneshot},
+    time::Instant,
+};
+use transport::Link;
+
+pub use interfaces::{Interface, LocalAddrFilter};
+pub use stats::Stats;
+
+pub use events::{DiscoveryEvent, Events, Lagged};
 
-type Callback = Box<dyn FnMut(&str, &Peer) + Send + 'static>;
+#[cfg(feature = "signing")]
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+#[cfg(feature = "signing")]
+pub use signing::{peer_id_from_key, Verification};
 
-pub(crate) type TxtData = BTreeMap<String, Option<String>>;
+type Callback = Box<dyn FnMut(&DiscoveryEvent) + Send + 'static>;
+type PeerFilter = Arc<dyn Fn(&str, &Peer) -> bool + Send + Sync + 'static>;
+
+/// How long a stopped discovery waits for its goodbye announcements to be sent.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) type TxtData = BTreeMap<String, Option<Vec<u8>>>;
 
 /// Errors that can occur when spawning a swarm discovery service.
+///
+/// Further variants may be added in future releases.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum SpawnError {
     #[error(transparent)]
     Sockets {
@@ -50,15 +93,52 @@ pub enum SpawnError {
         source: hickory_proto::ProtoError,
         service_name: Name,
     },
+    #[error("TXT attributes of peer {peer_id} take {size} bytes, more than the {budget} bytes available in a packet")]
+    AnnouncementTooLarge {
+        peer_id: String,
+        size: usize,
+        budget: usize,
+    },
 }
 
 /// Errors that can occur when validating a txt attribute.
+///
+/// Further variants may be added in future releases.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum TxtAttributeError {
     #[error("Key may not be empty")]
     EmptyKey,
     #[error("Key-value pair is too long, must be shorter than 254 bytes")]
     TooLong,
+    #[error("Key '{key}' is reserved for internal use")]
+    ReservedKey { key: String },
+    #[error("TXT attributes would take {size} bytes, more than the {budget} bytes available in a packet")]
+    TooLarge { size: usize, budget: usize },
+}
+
+/// Errors that can occur when validating a subtype.
+///
+/// Further variants may be added in future releases.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SubtypeError {
+    #[error("Subtype must not be empty")]
+    Empty,
+    #[error("Subtype '{subtype}' does not fit into a DNS label")]
+    TooLong { subtype: String },
+}
+
+/// Errors that can occur when changing the cadence or response rate.
+///
+/// Further variants may be added in future releases.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum TimingError {
+    #[error("Cadence must be greater than zero")]
+    ZeroCadence,
+    #[error("Cadence {tau:?} and response rate {phi}Hz yield less than one response per cycle")]
+    TooFewResponses { tau: Duration, phi: f32 },
 }
 
 /// Builder for a swarm discovery service.
@@ -98,10 +178,33 @@ pub struct Discoverer {
     peer_id: String,
     peers: BTreeMap<String, Peer>,
     callback: Callback,
+    peer_filter: PeerFilter,
     tau: Duration,
     phi: f32,
     class: IpClass,
     multicast_interfaces: Vec<Ipv4Addr>,
+    multicast_interfaces_v6: Vec<u32>,
+    auto_interfaces: bool,
+    interface_filter: InterfaceFilter,
+    auto_ports: BTreeSet<u16>,
+    local_addr_filter: LocalAddrFilter,
+    subtypes: BTreeSet<String>,
+    browse_subtype: Option<String>,
+    event_buffer: usize,
+    max_packet_size: usize,
+    liveness_events: bool,
+    auto_rename: bool,
+    rng_seed: Option<u64>,
+    #[cfg(feature = "serde")]
+    peer_cache: Option<std::path::PathBuf>,
+    #[cfg(feature = "signing")]
+    signing_key: Option<SigningKey>,
+    #[cfg(feature = "signing")]
+    verification: Verification,
+    #[cfg(feature = "swarm-secret")]
+    swarm_secret: Option<secret::SwarmSecret>,
+    #[cfg(feature = "test-util")]
+    memory_network: Option<(test_util::MemoryNetwork, IpAddr)>,
 }
 
 /// A peer discovered by the swarm discovery service.
@@ -113,6 +216,11 @@ pub struct Peer {
     addrs: Vec<(IpAddr, u16)>,
     last_seen: Instant,
     txt: TxtData,
+    verified: bool,
+    ttl: Duration,
+    /// subtypes seen announced in lower case, kept across responses not listing them until
+    /// withdrawn by a goodbye
+    subtypes: BTreeSet<String>,
 }
 
 impl Peer {
@@ -124,6 +232,9 @@ impl Peer {
             addrs: Default::default(),
             last_seen: Instant::now(),
             txt: Default::default(),
+            verified: false,
+            ttl: Duration::MAX,
+            subtypes: Default::default(),
         }
     }
 
@@ -134,7 +245,7 @@ impl Peer {
 
     /// Returns true if this peer has expired.
     pub fn is_expiry(&self) -> bool {
-        self.addrs.len() == 0
+        self.addrs.is_empty()
     }
 
     /// Return the age of this peer snapshot.
@@ -149,11 +260,58 @@ impl Peer {
     /// Returns an iterator of the TXT attributes set by the peer.
     ///
     /// See [`Discoverer::with_txt_attributes`] for details on the encoding of
-    /// these attributes.
+    /// these attributes. Attributes whose value is not valid UTF-8 are skipped,
+    /// see [`Peer::txt_attributes_bytes`].
     pub fn txt_attributes(&self) -> impl Iterator<Item = (&str, Option<&str>)> + '_ {
-        self.txt
-            .iter()
-            .map(|(k, v)| (k.as_str(), v.as_ref().map(|v| v.as_str())))
+        self.txt.iter().filter_map(|(k, v)| match v {
+            None => Some((k.as_str(), None)),
+            Some(v) => Some((k.as_str(), Some(std::str::from_utf8(v).ok()?))),
+        })
+    }
+
+    /// Returns an iterator of the TXT attributes set by the peer, with values as bytes.
+    ///
+    /// See [`Discoverer::with_txt_attributes_bytes`] for details on the encoding of
+    /// these attributes.
+    pub fn txt_attributes_bytes(&self) -> impl Iterator<Item = (&str, Option<&[u8]>)> + '_ {
+        self.txt.iter().map(|(k, v)| (k.as_str(), v.as_deref()))
+    }
+
+    /// Returns true if the last announcement of this peer carried a valid signature.
+    ///
+    /// This is only checked when enabled with `Discoverer::with_verification` (requires the
+    /// `signing` feature), otherwise it is always false.
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Returns an iterator of the subtypes this peer is announced under, in lower case.
+    ///
+    /// See [`Discoverer::with_subtypes`]. A subtype is listed until the peer withdraws it.
+    pub fn subtypes(&self) -> impl Iterator<Item = &str> + '_ {
+        self.subtypes.iter().map(String::as_str)
+    }
+
+    /// The time to live of the records in the last announcement of this peer.
+    ///
+    /// The peer is considered gone if no further announcement is received within this time,
+    /// or earlier if it falls silent while others keep responding. Peers running versions
+    /// before 0.7 announce a TTL of zero and are only expired when they fall silent.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns a copy of this snapshot in the form used to signal expiry to
+    /// callbacks registered with [`Discoverer::with_callback`].
+    pub(crate) fn expired(&self) -> Self {
+        Peer {
+            addrs: vec![],
+            last_seen: self.last_seen,
+            txt: Default::default(),
+            verified: self.verified,
+            ttl: Duration::ZERO,
+            subtypes: Default::default(),
+        }
     }
 
     /// Returns the value for a TXT attribute for this peer.
@@ -162,9 +320,21 @@ impl Peer {
     /// Returns `Some(None)` if the attribute is a boolean, i.e. has no value.
     /// Returns `Some(Some(value))` if the attribute has a value.
     ///
+    /// Values that are not valid UTF-8 are treated as missing, see [`Peer::txt_attribute_bytes`].
+    ///
     /// See [`Discoverer::with_txt_attributes`] for details on the encoding of
     /// these attributes.
     pub fn txt_attribute(&self, name: &str) -> Option<Option<&str>> {
+        match self.txt.get(name)? {
+            None => Some(None),
+            Some(value) => Some(Some(std::str::from_utf8(value).ok()?)),
+        }
+    }
+
+    /// Returns the value for a TXT attribute for this peer as bytes.
+    ///
+    /// Like [`Peer::txt_attribute`], but also returns values that are not valid UTF-8.
+    pub fn txt_attribute_bytes(&self, name: &str) -> Option<Option<&[u8]>> {
         self.txt.get(name).map(|x| x.as_deref())
     }
 }
@@ -227,20 +397,57 @@ impl Discoverer {
     ///
     /// The `name` is the name of the mDNS service, meaning that it will be discoverable under the name `_name._udp.local.`.
     /// The `peer_id` is the unique identifier of this peer, which will be discoverable under the name `peer_id._name._udp.local.`.
+    /// It may be any non-empty string, including dots; peer IDs longer than the 63 bytes of a DNS
+    /// label are abbreviated in the name and announced in full in the reserved TXT attribute `_id`.
     pub fn new(name: String, peer_id: String) -> Self {
         Self {
             name,
             protocol: Protocol::default(),
             peer_id,
             peers: BTreeMap::new(),
-            callback: Box::new(|_, _| {}),
+            callback: Box::new(|_| {}),
+            peer_filter: Arc::new(|_, _| true),
             tau: Duration::from_secs(10),
             phi: 1.0,
             class: IpClass::default(),
             multicast_interfaces: Vec::new(),
+            multicast_interfaces_v6: Vec::new(),
+            auto_interfaces: false,
+            interface_filter: Arc::new(|_| true),
+            auto_ports: BTreeSet::new(),
+            local_addr_filter: LocalAddrFilter::default(),
+            subtypes: BTreeSet::new(),
+            browse_subtype: None,
+            event_buffer: events::DEFAULT_EVENT_BUFFER,
+            max_packet_size: packets::DEFAULT_PACKET_SIZE,
+            liveness_events: false,
+            auto_rename: false,
+            rng_seed: None,
+            #[cfg(feature = "serde")]
+            peer_cache: None,
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            #[cfg(feature = "signing")]
+            verification: Verification::default(),
+            #[cfg(feature = "swarm-secret")]
+            swarm_secret: None,
+            #[cfg(feature = "test-util")]
+            memory_network: None,
         }
     }
 
+    /// Creates a new builder for a swarm discovery service that signs its announcements.
+    ///
+    /// The peer ID is derived from the public part of the given key (see [peer_id_from_key]),
+    /// which allows other peers to verify announcements with [Discoverer::with_verification].
+    /// The signature is transmitted as a TXT attribute, adding about 90 bytes to each response.
+    #[cfg(feature = "signing")]
+    pub fn new_signed(name: String, key: SigningKey) -> Self {
+        let mut this = Self::new(name, peer_id_from_key(&key.verifying_key()));
+        this.signing_key = Some(key);
+        this
+    }
+
     /// Creates a new builder with default cadence and response rate for human interactive applications.
     ///
     /// This sets τ=0.7sec and φ=2.5, see [Discoverer::new] for the `name` and `peer_id` arguments.
@@ -274,11 +481,34 @@ impl Discoverer {
         self
     }
 
+    /// Announce the given port on all addresses of this host, may be called multiple times with additive effect.
+    ///
+    /// The announced addresses are kept up to date while the discovery is running, so that
+    /// addresses are added and removed as network interfaces come and go (e.g. when a laptop
+    /// moves between networks). On Linux, changes are picked up as soon as they are reported
+    /// via netlink, on other platforms the interfaces are checked every five seconds.
+    ///
+    /// By default, loopback, link-local and virtual interface addresses are not announced,
+    /// see [Discoverer::with_local_addr_filter]; interfaces can also be excluded with
+    /// [Discoverer::with_interface_filter]. Addresses registered with [Discoverer::with_addrs]
+    /// are announced in addition.
+    pub fn with_port_on_all_interfaces(mut self, port: u16) -> Self {
+        self.auto_ports.insert(port);
+        self
+    }
+
+    /// Select which kinds of local addresses are announced by [Discoverer::with_port_on_all_interfaces].
+    pub fn with_local_addr_filter(mut self, filter: LocalAddrFilter) -> Self {
+        self.local_addr_filter = filter;
+        self
+    }
+
     /// Sets TXT attributes for this peer.
     ///
     /// This crate supports a single TXT record per peer, which contains a list
     /// of key-value pairs of UTF-8 strings. The value is optional: when missing,
-    /// the attribute is a flag, simply identified as being present.
+    /// the attribute is a flag, simply identified as being present. Values may also
+    /// be arbitrary bytes, see [Discoverer::with_txt_attributes_bytes].
     ///
     /// The formatting of the TXT record follows [RFC 6763], with the following
     /// differences to the RFC:
@@ -288,14 +518,38 @@ impl Discoverer {
     /// Key and value of each pair may not be longer than 254 bytes combined.
     /// Returns an error if the length is exceeded.
     ///
-    /// The total length of all attributes is not checked here. You should make sure
-    /// to keep the total length of all attributes at a few hundred bytes so that
-    /// the resulting DNS packet does not exceed the UDP MTU.
+    /// All attributes are sent in one TXT record, which unlike the rest of an announcement
+    /// cannot be split across packets (see [Discoverer::with_max_packet_size]). Returns
+    /// [TxtAttributeError::TooLarge] if they would not fit into a packet with the current
+    /// configuration; since the packet size, signing and swarm secret may still change, the
+    /// final configuration is checked again by [Discoverer::spawn].
     ///
     /// [RFC 6763]: https://datatracker.ietf.org/doc/html/rfc6763#section-6
     pub fn with_txt_attributes(
-        mut self,
+        self,
         attributes: impl IntoIterator<Item = (String, Option<String>)>,
+    ) -> Result<Self, TxtAttributeError> {
+        self.with_txt_attributes_bytes(
+            attributes
+                .into_iter()
+                .map(|(key, value)| (key, value.map(String::into_bytes))),
+        )
+    }
+
+    /// Sets TXT attributes with binary values for this peer.
+    ///
+    /// [RFC 6763] allows values to be arbitrary bytes, which is useful to carry compact binary
+    /// data like hashes or public keys without the overhead of a textual encoding. Keys are
+    /// still UTF-8 strings, while a value extends from the first `=` to the end of the
+    /// attribute and may contain any byte. Otherwise the same rules as for
+    /// [Discoverer::with_txt_attributes] apply.
+    ///
+    /// Received values are available via [Peer::txt_attribute_bytes].
+    ///
+    /// [RFC 6763]: https://datatracker.ietf.org/doc/html/rfc6763#section-6.5
+    pub fn with_txt_attributes_bytes(
+        mut self,
+        attributes: impl IntoIterator<Item = (String, Option<Vec<u8>>)>,
     ) -> Result<Self, TxtAttributeError> {
         let me = self
             .peers
@@ -305,19 +559,200 @@ impl Discoverer {
             validate_txt_attribute(&key, value.as_deref())?;
             me.txt.insert(key, value);
         }
+        if let Some((size, budget)) = self.txt_size_and_budget() {
+            if size > budget {
+                return Err(TxtAttributeError::TooLarge { size, budget });
+            }
+        }
+        Ok(self)
+    }
+
+    /// Announce this peer under the given subtypes of the service, may be called multiple times with additive effect.
+    ///
+    /// A subtype `worker` is announced as a PTR record `_worker._sub._name._udp.local.` pointing to
+    /// the instance name, as described in [RFC 6763 section 7.1]. Peers browsing a subtype (see
+    /// [Discoverer::with_browse_subtype]) only get responses from peers announcing it.
+    ///
+    /// Returns an error if a subtype is empty or longer than 62 bytes.
+    ///
+    /// [RFC 6763 section 7.1]: https://datatracker.ietf.org/doc/html/rfc6763#section-7.1
+    pub fn with_subtypes(
+        mut self,
+        subtypes: impl IntoIterator<Item = String>,
+    ) -> Result<Self, SubtypeError> {
+        for subtype in subtypes {
+            validate_subtype(&subtype)?;
+            self.subtypes.insert(subtype);
+        }
+        Ok(self)
+    }
+
+    /// Browse only for peers announcing the given subtype, see [Discoverer::with_subtypes].
+    ///
+    /// Queries are then sent for `_subtype._sub._name._udp.local.`, so that only peers of that
+    /// subtype respond and the response rate φ is spent on them alone. Queries of other peers for
+    /// a subtype this peer does not announce are ignored, while queries for the whole service are
+    /// still answered. Peers of other subtypes are still discovered when they respond to the
+    /// queries of others.
+    ///
+    /// Returns an error if the subtype is empty or longer than 62 bytes.
+    pub fn with_browse_subtype(mut self, subtype: String) -> Result<Self, SubtypeError> {
+        validate_subtype(&subtype)?;
+        self.browse_subtype = Some(subtype);
         Ok(self)
     }
 
-    /// Register a callback to be called when a peer is discovered or its addresses change.
+    /// Register a callback to be called when a peer is discovered or its addresses or TXT attributes change.
+    ///
+    /// Responses that do not change anything only trigger the callback when enabled with
+    /// [Discoverer::with_liveness_events].
     ///
     /// When a peer is removed, the callback will be called with an empty list of addresses.
     /// This happens after not receiving any responses for a time period greater than three
     /// times the estimated swarm size divided by the response frequency.
-    pub fn with_callback(mut self, callback: impl FnMut(&str, &Peer) + Send + 'static) -> Self {
+    ///
+    /// This is a simplified form of [Discoverer::with_event_callback], replacing any callback
+    /// registered previously.
+    pub fn with_callback(mut self, mut callback: impl FnMut(&str, &Peer) + Send + 'static) -> Self {
+        self.callback = Box::new(move |event| match event {
+            DiscoveryEvent::Expired { peer_id, peer } => callback(peer_id, &peer.expired()),
+            // not about other peers
+            DiscoveryEvent::Conflict { .. } | DiscoveryEvent::Renamed { .. } => {}
+            event => callback(event.peer_id(), event.peer()),
+        });
+        self
+    }
+
+    /// Register a callback to be called with every [DiscoveryEvent].
+    ///
+    /// In contrast to [Discoverer::with_callback], the events distinguish between newly
+    /// discovered, updated and expired peers, and expiry events carry the last known state
+    /// of the peer. This replaces any callback registered previously.
+    pub fn with_event_callback(
+        mut self,
+        callback: impl FnMut(&DiscoveryEvent) + Send + 'static,
+    ) -> Self {
         self.callback = Box::new(callback);
         self
     }
 
+    /// Set a filter that decides which discovered peers are of interest.
+    ///
+    /// The filter is called with the peer ID and the received state of a peer whenever a
+    /// response from it arrives, and should return `true` for peers that shall be kept.
+    /// Rejected peers are left out of [DropGuard::peers], do not trigger callbacks or events,
+    /// and do not count into the swarm size estimate that drives the timing of queries and
+    /// responses. A known peer that is rejected by a later response, e.g. because its TXT
+    /// attributes changed, is removed and reported as [DiscoveryEvent::Expired].
+    ///
+    /// Note that the local peer is subject to the filter as well. By default, all peers are kept.
+    ///
+    /// ```rust
+    /// # use swarm_discovery::Discoverer;
+    /// let discoverer = Discoverer::new("swarm".to_owned(), "peer_id42".to_owned())
+    ///     .with_peer_filter(|_peer_id, peer| peer.txt_attribute("version") == Some(Some("2")));
+    /// ```
+    pub fn with_peer_filter(
+        mut self,
+        filter: impl Fn(&str, &Peer) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.peer_filter = Arc::new(filter);
+        self
+    }
+
+    /// Emit a [DiscoveryEvent::Refreshed] for every response that does not change a known peer.
+    ///
+    /// By default, callbacks and event streams are only notified when a peer is discovered,
+    /// changes its addresses or TXT attributes, or expires. Enable this if you rely on the
+    /// steady flow of responses as a liveness signal.
+    pub fn with_liveness_events(mut self, enabled: bool) -> Self {
+        self.liveness_events = enabled;
+        self
+    }
+
+    /// Keep the table of discovered peers in the given file across restarts.
+    ///
+    /// After a restart the discovery would otherwise assume a swarm of one, so its queries and
+    /// responses are timed for a tiny swarm until the other peers have been seen again. With a
+    /// peer cache, the table is written to the file every minute and when the discovery is
+    /// stopped, and reloaded when it is spawned. Reloaded peers are tentative: they count into
+    /// the swarm size estimate right away, but are neither listed by [DropGuard::peers] nor
+    /// reported via callbacks or events until a response from them arrives. Tentative peers
+    /// that stay silent are dropped by the usual expiry after the grace period or their TTL.
+    ///
+    /// Failures to read or write the file are logged and otherwise ignored.
+    #[cfg(feature = "serde")]
+    pub fn with_peer_cache(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.peer_cache = Some(path.into());
+        self
+    }
+
+    /// Pick a new peer ID when the configured one is already used by another instance.
+    ///
+    /// Before announcing, the discovery probes for its peer ID as described in
+    /// [RFC 6762 section 8](https://datatracker.ietf.org/doc/html/rfc6762#section-8), and
+    /// later responses for its peer ID with foreign addresses are detected as well. Each such
+    /// conflict yields a [DiscoveryEvent::Conflict]. With this option enabled the conflict is
+    /// resolved by appending `-2`, `-3`, … to the configured peer ID, which is reported as
+    /// [DiscoveryEvent::Renamed]. Signed peer IDs are never renamed.
+    ///
+    /// The default is to keep the peer ID.
+    pub fn with_auto_rename(mut self, enabled: bool) -> Self {
+        self.auto_rename = enabled;
+        self
+    }
+
+    /// Check the signatures of received announcements, see [Verification].
+    ///
+    /// The default is [Verification::Off].
+    #[cfg(feature = "signing")]
+    pub fn with_verification(mut self, verification: Verification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Restrict the swarm to peers holding the given secret.
+    ///
+    /// All sent queries and responses are authenticated with an HMAC over their records, a
+    /// random per-packet nonce and the time of sending, adding about 100 bytes to each packet.
+    /// Packets for this service that are not authenticated with the same secret are silently
+    /// ignored, so several swarms can use the same service name without seeing each other.
+    ///
+    /// Packets sent more than 30 seconds before or after the local time are ignored as well, so
+    /// the clocks of all peers must agree to within this margin; recorded packets can only be
+    /// replayed within it. Announcements are not encrypted, peers without a secret still
+    /// discover members of the swarm.
+    #[cfg(feature = "swarm-secret")]
+    pub fn with_swarm_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.swarm_secret = Some(secret::SwarmSecret::new(secret.as_ref()));
+        self
+    }
+
+    /// Set the number of events buffered for each stream obtained from [DropGuard::events].
+    ///
+    /// When a stream is not polled fast enough, the oldest events are dropped and the stream
+    /// reports this by yielding a [Lagged] error before continuing with the remaining events.
+    ///
+    /// The default is 256, values below 1 are treated as 1.
+    pub fn with_event_buffer(mut self, capacity: usize) -> Self {
+        self.event_buffer = capacity;
+        self
+    }
+
+    /// Set the maximum size of sent packets in bytes of DNS payload.
+    ///
+    /// Announcements that do not fit, e.g. due to many addresses or TXT attributes, are split
+    /// across several packets and reassembled by the receivers; the same applies to the list of
+    /// known answers in queries (see RFC 6762 section 17). Received packets may always be as
+    /// large as mDNS permits.
+    ///
+    /// The default of 1452 fits into a standard Ethernet frame also with IPv6; on networks with
+    /// jumbo frames, this can be raised. Values are clamped to the range from 512 to 8952.
+    pub fn with_max_packet_size(mut self, size: usize) -> Self {
+        self.max_packet_size = size.clamp(packets::MIN_PACKET_SIZE, packets::MAX_PACKET_SIZE);
+        self
+    }
+
     /// Set the discovery time target.
     ///
     /// After roughly this time a new peer should have discovered some parts of the swarm.
@@ -364,22 +799,205 @@ impl Discoverer {
     /// Provide a list of local IPv4 addresses to send multicast messages on specific interfaces.
     ///
     /// This improves discovery in multi-homed environments where peers may be on different
-    /// network segments. Note that this only affects IPv4; IPv6 multicast always uses the
-    /// default interface.
+    /// network segments. Note that this only affects IPv4, see
+    /// [Discoverer::with_multicast_interfaces_v6] for IPv6.
     pub fn with_multicast_interfaces_v4(mut self, interfaces: Vec<Ipv4Addr>) -> Self {
         self.multicast_interfaces = interfaces;
         self
     }
 
+    /// Set which IPv6 interfaces to use for sending multicast messages, given by interface index.
+    ///
+    /// By default (empty vector), multicast messages are sent only on the default interface.
+    /// Provide a list of interface indexes (as returned by `if_nametoindex`) to send multicast
+    /// messages on specific links; the multicast group is also joined on each of them so that
+    /// messages from all these links are received.
+    ///
+    /// This requires an IPv6 socket, i.e. it has no effect with [IpClass::V4Only].
+    pub fn with_multicast_interfaces_v6(mut self, interfaces: Vec<u32>) -> Self {
+        self.multicast_interfaces_v6 = interfaces;
+        self
+    }
+
+    /// Automatically track the local network interfaces used for multicast messages.
+    ///
+    /// With this setting, multicast messages are sent and received on all non-loopback
+    /// interfaces, which are added and removed as they come and go (just like
+    /// [DropGuard::add_interface_v4] and [DropGuard::add_interface_v6] would do).
+    /// On Linux, changes are picked up as soon as they are reported via netlink, on other
+    /// platforms the interfaces are checked every five seconds.
+    ///
+    /// Use [Discoverer::with_interface_filter] to exclude interfaces, e.g. container bridges.
+    pub fn with_auto_interfaces(mut self) -> Self {
+        self.auto_interfaces = true;
+        self
+    }
+
+    /// Set a filter that decides which interface addresses are used by the discovery service.
+    ///
+    /// The filter is called for each address of each local interface and should return `true`
+    /// for those that shall be used. It is consulted by [Discoverer::with_auto_interfaces] and
+    /// [Discoverer::with_port_on_all_interfaces]. By default, all interfaces are used.
+    ///
+    /// ```rust
+    /// # use swarm_discovery::Discoverer;
+    /// let discoverer = Discoverer::new("swarm".to_owned(), "peer_id42".to_owned())
+    ///     .with_auto_interfaces()
+    ///     .with_interface_filter(|iface| {
+    ///         !iface.name().starts_with("docker") && !iface.name().starts_with("br-")
+    ///     });
+    /// ```
+    pub fn with_interface_filter(
+        mut self,
+        filter: impl Fn(&Interface) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.interface_filter = Arc::new(filter);
+        self
+    }
+
+    /// Attach the discovery service to a simulated network instead of the real one.
+    ///
+    /// No sockets are opened, all packets are exchanged with the other discovery services
+    /// attached to the same [MemoryNetwork](test_util::MemoryNetwork), which see them as
+    /// coming from the given address. Interface-related settings have no effect.
+    #[cfg(feature = "test-util")]
+    pub fn with_memory_network(mut self, network: &test_util::MemoryNetwork, addr: IpAddr) -> Self {
+        self.memory_network = Some((network.clone(), addr));
+        self
+    }
+
+    /// Seed the random number generator used for randomizing query and response delays.
+    ///
+    /// This makes the behaviour of a swarm reproducible in tests when used together with
+    /// [Discoverer::with_memory_network] and Tokio's paused clock. Each discovery service in
+    /// the swarm should get a different seed.
+    #[cfg(feature = "test-util")]
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
     /// Start the discovery service.
     ///
     /// This will spawn asynchronous tasks and return a guard which will stop the discovery when dropped.
-    /// Changing the configuration is done by stopping the discovery and starting a new one.
+    /// Local addresses, TXT attributes, cadence and response rate can be changed via the guard while
+    /// the discovery is running; changing other configuration is done by stopping the discovery and
+    /// starting a new one.
+    #[allow(clippy::result_large_err)]
     pub fn spawn(self, handle: &Handle) -> Result<DropGuard, SpawnError> {
+        let mut guards = Self::spawn_many([self], handle)?;
+        Ok(guards.pop().expect("one guard per discoverer"))
+    }
+
+    /// Start several discovery services that share the same sockets.
+    ///
+    /// Each service has its own name, peer ID, addresses, TXT attributes, callback and
+    /// timings, but received packets are parsed only once and dispatched to all services.
+    /// Returns one guard per service, in the same order; dropping a guard stops only its
+    /// service, the sockets are closed when all guards have been dropped.
+    ///
+    /// The sockets are configured by the first discoverer, i.e. its [IpClass], multicast
+    /// interfaces, [Discoverer::with_auto_interfaces] and interface filter apply to all
+    /// services. These settings are ignored on the other discoverers.
+    ///
+    /// ```rust
+    /// # use swarm_discovery::Discoverer;
+    /// # use std::net::{IpAddr, Ipv4Addr};
+    /// # let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    /// let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    /// let guards = Discoverer::spawn_many(
+    ///     [
+    ///         Discoverer::new("a".to_owned(), "peer_a".to_owned()).with_addrs(1234, [addr]),
+    ///         Discoverer::new("b".to_owned(), "peer_b".to_owned()).with_addrs(1235, [addr]),
+    ///     ],
+    ///     rt.handle(),
+    /// )
+    /// .expect("discoverer spawn");
+    /// assert_eq!(guards.len(), 2);
+    /// ```
+    #[allow(clippy::result_large_err)]
+    pub fn spawn_many(
+        discoverers: impl IntoIterator<Item = Discoverer>,
+        handle: &Handle,
+    ) -> Result<Vec<DropGuard>, SpawnError> {
+        let discoverers = discoverers.into_iter().collect::<Vec<_>>();
+        let Some(first) = discoverers.first() else {
+            return Ok(Vec::new());
+        };
         let _entered = handle.enter();
-        let sockets = Sockets::new(self.class, self.multicast_interfaces.clone())?;
-        tracing::trace!(?sockets, "created new sockets");
+        let link = first.link()?;
+        tracing::trace!(?link, "created new link");
+
+        let mut services = Vec::with_capacity(discoverers.len());
+        let mut txts = Vec::with_capacity(discoverers.len());
+        for discoverer in discoverers {
+            let service_name = discoverer.service_name()?;
+            let (size, budget) = discoverer
+                .txt_size_and_budget()
+                .expect("names were checked above");
+            if size > budget {
+                return Err(SpawnError::AnnouncementTooLarge {
+                    peer_id: discoverer.peer_id,
+                    size,
+                    budget,
+                });
+            }
+            let txt = discoverer
+                .peers
+                .get(&discoverer.peer_id)
+                .map(|peer| peer.txt.clone())
+                .unwrap_or_default();
+            txts.push((
+                Mutex::new(txt),
+                budget,
+                Mutex::new((discoverer.tau, discoverer.phi)),
+            ));
+            let (events, _) = broadcast::channel(discoverer.event_buffer.max(1));
+            let stats = Arc::new(stats::Counters::new(discoverer.name.clone()));
+            services.push(guardian::Service {
+                discoverer,
+                service_name,
+                events,
+                stats,
+            });
+        }
+        let events = services
+            .iter()
+            .map(|service| (service.events.clone(), service.stats.clone()))
+            .collect::<Vec<_>>();
+
+        let rt_handle = handle.clone();
+        let rt = AcTokio::from_handle("swarm-discovery", handle.clone());
+        let SupervisionRef { me, handle } = rt.spawn_actor("guardian", move |ctx| {
+            guardian::guardian(ctx, link, services)
+        });
+        let shared = Arc::new(Shared {
+            task: Some(handle),
+            handle: rt_handle,
+            _rt: rt,
+        });
 
+        Ok(events
+            .into_iter()
+            .zip(txts)
+            .enumerate()
+            .map(
+                |(service, ((events, stats), (txt, txt_budget, timings)))| DropGuard {
+                    _shared: shared.clone(),
+                    aref: me.clone(),
+                    service,
+                    events,
+                    stats,
+                    txt,
+                    txt_budget,
+                    timings,
+                },
+            )
+            .collect())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn service_name(&self) -> Result<Name, SpawnError> {
         let service_name = Name::from_str(&format!("_{}.{}.local.", self.name, self.protocol))
             .map_err(|source| SpawnError::ServiceName {
                 source,
@@ -387,59 +1005,220 @@ impl Discoverer {
                 protocol: self.protocol,
             })?;
         // need to test this here so it won't fail in the actor
-        Name::from_str(&self.peer_id)
-            .map_err(|source| SpawnError::NameFromPeerId {
-                source,
-                peer_id: self.peer_id.clone(),
-            })?
-            .append_domain(&service_name)
+        let label = names::label(&self.peer_id).map_err(|source| SpawnError::NameFromPeerId {
+            source,
+            peer_id: self.peer_id.clone(),
+        })?;
+        service_name
+            .prepend_label(label)
             .map_err(|source| SpawnError::AppendServiceName {
                 source,
                 service_name: service_name.clone(),
             })?;
+        Ok(service_name)
+    }
 
-        let rt = AcTokio::from_handle("swarm-discovery", handle.clone());
-        let SupervisionRef { me, handle } = rt.spawn_actor("guardian", move |ctx| {
-            guardian::guardian(ctx, self, sockets, service_name)
-        });
+    /// The space for the DNS message in a packet, leaving room for the authentication by a
+    /// swarm secret.
+    #[cfg_attr(not(feature = "swarm-secret"), allow(unused_variables))]
+    pub(crate) fn payload_size(&self, service_name: &Name) -> usize {
+        #[cfg(feature = "swarm-secret")]
+        if self.swarm_secret.is_some() {
+            return self.max_packet_size - secret::overhead(service_name);
+        }
+        self.max_packet_size
+    }
 
-        Ok(DropGuard {
-            task: Some(handle),
-            aref: me,
-            _rt: rt,
-        })
+    /// The encoded size of the TXT attributes and the space available for them in a packet.
+    ///
+    /// Returns `None` if the service name or peer ID are invalid.
+    fn txt_size_and_budget(&self) -> Option<(usize, usize)> {
+        let service_name = self.service_name().ok()?;
+        let instance = names::instance_name(&self.peer_id, &service_name).ok()?;
+        let mut reserved = 0;
+        if names::is_abbreviated(&self.peer_id) {
+            reserved += names::peer_id_parts(&self.peer_id)
+                .map(|part| 1 + part.len())
+                .sum::<usize>();
+        }
+        #[cfg(feature = "signing")]
+        if self.signing_key.is_some() {
+            reserved += signing::SIGNATURE_ATTRIBUTE_LEN;
+        }
+        let budget = packets::txt_budget(self.payload_size(&service_name), &instance, reserved);
+        let size = self
+            .peers
+            .get(&self.peer_id)
+            .map(|peer| txt_len(&peer.txt))
+            .unwrap_or_default();
+        Some((size, budget))
+    }
+
+    fn link(&self) -> Result<Link, SocketError> {
+        #[cfg(feature = "test-util")]
+        if let Some((network, addr)) = &self.memory_network {
+            return Ok(Link::Memory(network.endpoint(*addr)));
+        }
+        Ok(Link::Udp(Sockets::new(
+            self.class,
+            self.multicast_interfaces.clone(),
+            self.multicast_interfaces_v6.clone(),
+        )?))
     }
 }
 
 /// A guard which will keep the discovery running until it is dropped.
 ///
 /// You can also use this guard to modify the local addresses while the discovery is running.
+/// When dropped, a goodbye announcement is sent so that other peers forget this one right away.
 #[must_use = "dropping this value will stop the mDNS discovery"]
 pub struct DropGuard {
+    _shared: Arc<Shared>,
+    aref: ActoRef<(usize, guardian::Input)>,
+    service: usize,
+    events: EventSender,
+    stats: Arc<stats::Counters>,
+    /// the TXT attributes of the local peer, to check their size before sending them on
+    txt: Mutex<TxtData>,
+    txt_budget: usize,
+    /// cadence and response rate, to check their product before sending them on
+    timings: Mutex<(Duration, f32)>,
+}
+
+/// The part of a discovery instance shared by all its services, stopped when the last
+/// [DropGuard] is dropped.
+struct Shared {
     task: Option<TokioJoinHandle<()>>,
-    aref: ActoRef<guardian::Input>,
+    handle: Handle,
     _rt: AcTokio,
 }
 
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let mut task = self.task.take().unwrap();
+        // give the services the chance to send their goodbye announcements
+        self.handle.spawn(async move {
+            let stopped = std::future::poll_fn(|cx| ActoHandle::poll(Pin::new(&mut task), cx));
+            if tokio::time::timeout(GOODBYE_TIMEOUT, stopped)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        });
+    }
+}
+
 impl DropGuard {
-    /// Remove all local addresses and stop advertising.
+    fn send(&self, input: guardian::Input) {
+        self.aref.send((self.service, input));
+    }
+
+    /// Obtain a stream of discovery events.
+    ///
+    /// The stream yields every [DiscoveryEvent] that is also passed to the callback registered
+    /// with [Discoverer::with_event_callback], starting with the first event after this call.
+    /// Each stream buffers a bounded number of events (see [Discoverer::with_event_buffer]); if
+    /// it falls behind, the oldest events are dropped and a [Lagged] error is yielded in their
+    /// place.
+    ///
+    /// The stream ends when this guard is dropped.
+    pub fn events(&self) -> Events {
+        Events::new(&self.events)
+    }
+
+    /// Obtain a snapshot of all currently known peers.
+    ///
+    /// This includes the local peer once its own announcements have been received.
+    /// Returns an empty map if the discovery service has stopped.
+    pub async fn peers(&self) -> BTreeMap<String, Peer> {
+        let (tx, rx) = oneshot::channel();
+        self.send(guardian::Input::GetPeers(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    /// Look up a currently known peer by its ID.
+    ///
+    /// Returns `None` if the peer is not known or the discovery service has stopped.
+    pub async fn peer(&self, peer_id: &str) -> Option<Peer> {
+        let (tx, rx) = oneshot::channel();
+        self.send(guardian::Input::GetPeer(peer_id.to_owned(), tx));
+        rx.await.ok().flatten()
+    }
+
+    /// The encoded size in bytes of the announcement currently sent by this peer.
+    ///
+    /// This is the sum over all packets if the announcement is split (see
+    /// [Discoverer::with_max_packet_size]), including the authentication by a swarm secret.
+    /// Returns zero if there is nothing to announce or the discovery service has stopped.
+    pub async fn announcement_size(&self) -> usize {
+        let (tx, rx) = oneshot::channel();
+        self.send(guardian::Input::GetAnnouncementSize(tx));
+        rx.await.unwrap_or_default()
+    }
+
+    /// A snapshot of the activity of this discovery service.
+    ///
+    /// This shows how the rate limiting described in the README plays out: the swarm size
+    /// estimate and response delay driving the timing, how many queries and responses were
+    /// sent and received, how many responses were suppressed, and the resulting traffic.
+    /// With the `metrics` feature, the same figures are reported to the installed recorder
+    /// of the [metrics](https://docs.rs/metrics) crate, labelled with the service name.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Change the discovery time target while the discovery is running.
+    ///
+    /// See [Discoverer::with_cadence] for details. A pending query is rescheduled so that the
+    /// new cadence takes effect immediately, the known peers are retained.
+    ///
+    /// The cadence must be positive and its product with the response rate at least 1,
+    /// otherwise the timings are left unchanged.
+    pub fn set_cadence(&self, tau: Duration) -> Result<(), TimingError> {
+        let mut timings = self.timings.lock().unwrap();
+        validate_timings(tau, timings.1)?;
+        timings.0 = tau;
+        self.send(guardian::Input::SetCadence(tau));
+        Ok(())
+    }
+
+    /// Change the response frequency target while the discovery is running.
+    ///
+    /// See [Discoverer::with_response_rate] for details. This takes effect with the next
+    /// query–response cycle, the known peers are retained.
+    ///
+    /// The product of cadence and response rate must be at least 1, otherwise the timings are
+    /// left unchanged.
+    pub fn set_response_rate(&self, phi: f32) -> Result<(), TimingError> {
+        let mut timings = self.timings.lock().unwrap();
+        validate_timings(timings.0, phi)?;
+        timings.1 = phi;
+        self.send(guardian::Input::SetResponseRate(phi));
+        Ok(())
+    }
+
+    /// Remove all local addresses and TXT attributes and stop advertising.
+    ///
+    /// A goodbye announcement is sent so that other peers forget this one right away.
     pub fn remove_all(&self) {
-        self.aref.send(guardian::Input::RemoveAll);
+        self.txt.lock().unwrap().clear();
+        self.send(guardian::Input::Update(Update::RemoveAll));
     }
 
     /// Remove a specific port from the local addresses.
     pub fn remove_port(&self, port: u16) {
-        self.aref.send(guardian::Input::RemovePort(port));
+        self.send(guardian::Input::Update(Update::RemovePort(port)));
     }
 
     /// Remove a specific address from the local addresses.
     pub fn remove_addr(&self, addr: IpAddr) {
-        self.aref.send(guardian::Input::RemoveAddr(addr));
+        self.send(guardian::Input::Update(Update::RemoveAddr(addr)));
     }
 
     /// Add a port and addresses to the local addresses.
     pub fn add(&self, port: u16, addrs: Vec<IpAddr>) {
-        self.aref.send(guardian::Input::AddAddr(port, addrs));
+        self.send(guardian::Input::Update(Update::AddAddr(port, addrs)));
     }
 
     /// Sets a TXT attribute for this peer.
@@ -450,22 +1229,61 @@ impl DropGuard {
     /// Key and value together may not be longer than 254 bytes. Returns an
     /// error if the length is exceeded.
     ///
-    /// The total length of all attributes is not checked here. You should make sure
-    /// to keep the total length of all attributes at a few hundred bytes so that
-    /// the resulting DNS packet does not exceed the UDP MTU.
+    /// Returns [TxtAttributeError::TooLarge] and leaves the attributes unchanged if all
+    /// attributes together would no longer fit into a packet.
     pub fn set_txt_attribute(
         &self,
         key: String,
         value: Option<String>,
+    ) -> Result<(), TxtAttributeError> {
+        self.set_txt_attribute_bytes(key, value.map(String::into_bytes))
+    }
+
+    /// Sets a TXT attribute with a binary value for this peer.
+    ///
+    /// See [`Discoverer::with_txt_attributes_bytes`] for details on the encoding of
+    /// binary values, otherwise this behaves like [`DropGuard::set_txt_attribute`].
+    pub fn set_txt_attribute_bytes(
+        &self,
+        key: String,
+        value: Option<Vec<u8>>,
     ) -> Result<(), TxtAttributeError> {
         validate_txt_attribute(&key, value.as_deref())?;
-        self.aref.send(guardian::Input::SetTxt(key, value));
+        let mut txt = self.txt.lock().unwrap();
+        let previous = txt.insert(key.clone(), value.clone());
+        let size = txt_len(&txt);
+        if size > self.txt_budget {
+            match previous {
+                Some(previous) => txt.insert(key, previous),
+                None => txt.remove(&key),
+            };
+            return Err(TxtAttributeError::TooLarge {
+                size,
+                budget: self.txt_budget,
+            });
+        }
+        self.send(guardian::Input::Update(Update::SetTxt(key, value)));
         Ok(())
     }
 
     /// Removes a TXT attribute.
     pub fn remove_txt_attribute(&self, key: String) {
-        self.aref.send(guardian::Input::RemoveTxt(key));
+        self.txt.lock().unwrap().remove(&key);
+        self.send(guardian::Input::Update(Update::RemoveTxt(key)));
+    }
+
+    /// Announce this peer under an additional subtype, see [Discoverer::with_subtypes].
+    pub fn add_subtype(&self, subtype: String) -> Result<(), SubtypeError> {
+        validate_subtype(&subtype)?;
+        self.send(guardian::Input::Update(Update::AddSubtype(subtype)));
+        Ok(())
+    }
+
+    /// Stop announcing this peer under a subtype.
+    ///
+    /// A goodbye announcement for the subtype is sent so that browsers forget it right away.
+    pub fn remove_subtype(&self, subtype: String) {
+        self.send(guardian::Input::Update(Update::RemoveSubtype(subtype)));
     }
 
     /// Add a new IPv4 interface for multicast operations.
@@ -474,32 +1292,80 @@ impl DropGuard {
     /// has started. Useful for systems where network interfaces may come up after
     /// the application starts.
     ///
-    /// Note: This only affects IPv4. IPv6 multicast always uses the default interface.
+    /// Note: This only affects IPv4, see [DropGuard::add_interface_v6] for IPv6.
     pub fn add_interface_v4(&self, interface: Ipv4Addr) {
-        self.aref
-            .send(guardian::Input::AddInterface(IpAddr::V4(interface)));
+        self.send(guardian::Input::AddInterface(IpAddr::V4(interface)));
     }
 
     /// Remove an IPv4 interface from multicast operations.
     ///
     /// This stops sending multicast messages on the specified interface.
     ///
-    /// Note: This only affects IPv4. IPv6 multicast always uses the default interface.
+    /// Note: This only affects IPv4, see [DropGuard::remove_interface_v6] for IPv6.
     pub fn remove_interface_v4(&self, interface: Ipv4Addr) {
-        self.aref
-            .send(guardian::Input::RemoveInterface(IpAddr::V4(interface)));
+        self.send(guardian::Input::RemoveInterface(IpAddr::V4(interface)));
+    }
+
+    /// Add a new IPv6 interface, given by its index, for multicast operations.
+    ///
+    /// This is the IPv6 counterpart of [DropGuard::add_interface_v4], see also
+    /// [Discoverer::with_multicast_interfaces_v6].
+    pub fn add_interface_v6(&self, interface_index: u32) {
+        self.send(guardian::Input::AddInterfaceV6(interface_index));
+    }
+
+    /// Remove an IPv6 interface, given by its index, from multicast operations.
+    ///
+    /// This stops sending multicast messages on the specified interface and leaves the
+    /// multicast group on it.
+    pub fn remove_interface_v6(&self, interface_index: u32) {
+        self.send(guardian::Input::RemoveInterfaceV6(interface_index));
     }
 }
 
 impl Drop for DropGuard {
     fn drop(&mut self) {
-        self.task.take().unwrap().abort();
+        // the service says goodbye, the whole instance stops after the last one
+        self.send(guardian::Input::Stop);
+    }
+}
+
+/// The encoded size of TXT attributes, each being a string prefixed by its length.
+fn txt_len(txt: &TxtData) -> usize {
+    txt.iter()
+        .map(|(key, value)| 1 + key.len() + value.as_ref().map_or(0, |v| 1 + v.len()))
+        .sum()
+}
+
+fn validate_subtype(subtype: &str) -> Result<(), SubtypeError> {
+    if subtype.is_empty() {
+        Err(SubtypeError::Empty)
+    } else if names::subtype_label(subtype).is_err() {
+        Err(SubtypeError::TooLong {
+            subtype: subtype.to_owned(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_timings(tau: Duration, phi: f32) -> Result<(), TimingError> {
+    if tau.is_zero() {
+        Err(TimingError::ZeroCadence)
+    } else if tau.as_secs_f32() * phi < 1.0 || phi.is_nan() {
+        Err(TimingError::TooFewResponses { tau, phi })
+    } else {
+        Ok(())
     }
 }
 
-fn validate_txt_attribute(key: &str, value: Option<&str>) -> Result<(), TxtAttributeError> {
+fn validate_txt_attribute(key: &str, value: Option<&[u8]>) -> Result<(), TxtAttributeError> {
     if key.is_empty() {
         Err(TxtAttributeError::EmptyKey)
+    } else if key == names::SIGNATURE_KEY || key == names::PEER_ID_KEY {
+        Err(TxtAttributeError::ReservedKey {
+            key: key.to_owned(),
+        })
     } else if key.len() + value.as_ref().map(|v| v.len()).unwrap_or_default() > 254 {
         Err(TxtAttributeError::TooLong)
     } else {
@@ -513,6 +1379,50 @@ mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr};
     use tokio::sync::mpsc;
 
+    #[test]
+    fn test_payload_size() {
+        let service_name = Name::from_ascii("_swarm._udp.local.").unwrap();
+        let discoverer = || Discoverer::new("swarm".to_owned(), "peer".to_owned());
+        let size = |discoverer: Discoverer| discoverer.payload_size(&service_name);
+        assert_eq!(size(discoverer()), 1452);
+        assert_eq!(size(discoverer().with_max_packet_size(1)), 512);
+        assert_eq!(size(discoverer().with_max_packet_size(usize::MAX)), 8952);
+        #[cfg(feature = "swarm-secret")]
+        assert_eq!(
+            size(discoverer().with_swarm_secret("secret")),
+            1452 - secret::overhead(&service_name)
+        );
+    }
+
+    #[test]
+    fn test_txt_budget() {
+        let budget = |peer_id: &str| {
+            let discoverer = Discoverer::new("swarm".to_owned(), peer_id.to_owned());
+            discoverer.txt_size_and_budget().unwrap().1
+        };
+        // header, owner name and fixed fields of the TXT record
+        let short = budget("peer");
+        assert_eq!(short, 1452 - 12 - "peer._swarm._udp.local.".len() - 1 - 10);
+        // an abbreviated peer ID takes a label of 63 bytes plus the strings of the full ID
+        let long = "x".repeat(300);
+        let parts = 2 * (1 + names::PEER_ID_KEY.len() + 1) + 300;
+        assert_eq!(budget(&long), short - (63 - 4) - parts);
+
+        // attributes `kN=…` of at most 255 bytes plus their length byte, filling the budget
+        let attributes = (0..)
+            .map(|i| (i * 256, format!("k{i}")))
+            .take_while(|(offset, _)| *offset < short)
+            .map(|(offset, key)| {
+                let len = (short - offset).min(256) - 1 - key.len() - 1;
+                (key, Some(vec![0; len]))
+            })
+            .collect::<Vec<_>>();
+        let full = Discoverer::new("swarm".to_owned(), "peer".to_owned())
+            .with_txt_attributes_bytes(attributes)
+            .unwrap();
+        assert_eq!(full.txt_size_and_budget(), Some((short, short)));
+    }
+
     #[tokio::test]
     async fn test_change_addresses() {
         let handle = tokio::runtime::Handle::current();
@@ -588,4 +1498,233 @@ mod tests {
         // Stop the discoverers
         drop(guard1);
     }
+
+    #[tokio::test]
+    async fn test_events_stream() {
+        use tokio_stream::StreamExt;
+
+        let handle = tokio::runtime::Handle::current();
+
+        let _guard1 = Discoverer::new("test_events".to_string(), "events_peer1".to_string())
+            .with_addrs(8001, vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))])
+            .with_cadence(Duration::from_secs(1))
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer1");
+
+        let guard2 = Discoverer::new("test_events".to_string(), "events_peer2".to_string())
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer2");
+        let mut events = guard2.events();
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.next().await {
+                    Some(Ok(DiscoveryEvent::Discovered { peer_id, peer }))
+                        if peer_id == "events_peer1" =>
+                    {
+                        return peer
+                    }
+                    Some(_) => {}
+                    None => panic!("event stream ended"),
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for event");
+        assert_eq!(
+            peer.addrs(),
+            &[(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8001)]
+        );
+
+        drop(guard2);
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multicast_interfaces_v6() {
+        use tokio_stream::StreamExt;
+
+        let handle = tokio::runtime::Handle::current();
+
+        let Some(index) = if_addrs::get_if_addrs()
+            .expect("get_if_addrs")
+            .into_iter()
+            .filter(|iface| !iface.is_loopback() && iface.ip().is_ipv6())
+            .find_map(|iface| iface.index)
+        else {
+            eprintln!("skipping test_multicast_interfaces_v6: no non-loopback interface with IPv6");
+            return;
+        };
+
+        let _guard1 = Discoverer::new("test_v6".to_string(), "v6_peer1".to_string())
+            .with_ip_class(IpClass::V6Only)
+            .with_multicast_interfaces_v6(vec![index])
+            .with_addrs(8003, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)])
+            .with_cadence(Duration::from_secs(1))
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer1");
+
+        let guard2 = Discoverer::new("test_v6".to_string(), "v6_peer2".to_string())
+            .with_ip_class(IpClass::V6Only)
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer2");
+        let mut events = guard2.events();
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.next().await {
+                    Some(Ok(DiscoveryEvent::Discovered { peer_id, peer }))
+                        if peer_id == "v6_peer1" =>
+                    {
+                        return peer
+                    }
+                    Some(_) => {}
+                    None => panic!("event stream ended"),
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for discovery over IPv6 interface");
+        assert_eq!(peer.addrs(), &[(IpAddr::V6(Ipv6Addr::LOCALHOST), 8003)]);
+    }
+
+    #[tokio::test]
+    async fn test_auto_interfaces() {
+        use tokio_stream::StreamExt;
+
+        let handle = tokio::runtime::Handle::current();
+
+        let Some(watched) = if_addrs::get_if_addrs()
+            .expect("get_if_addrs")
+            .into_iter()
+            .find(|iface| !iface.is_loopback() && iface.ip().is_ipv4())
+        else {
+            eprintln!("skipping test_auto_interfaces: no non-loopback IPv4 interface");
+            return;
+        };
+        let name = watched.name.clone();
+        let _guard1 = Discoverer::new("test_auto".to_string(), "auto_peer1".to_string())
+            .with_port_on_all_interfaces(8004)
+            .with_auto_interfaces()
+            .with_interface_filter(move |iface| iface.name() == name)
+            .with_cadence(Duration::from_secs(1))
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer1");
+
+        let guard2 = Discoverer::new("test_auto".to_string(), "auto_peer2".to_string())
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer2");
+        let mut events = guard2.events();
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.next().await {
+                    Some(Ok(DiscoveryEvent::Discovered { peer_id, peer }))
+                        if peer_id == "auto_peer1" =>
+                    {
+                        return peer
+                    }
+                    Some(_) => {}
+                    None => panic!("event stream ended"),
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for discovery");
+        // only the interface passing the filter is watched and announced
+        let watched_ips = if_addrs::get_if_addrs()
+            .expect("get_if_addrs")
+            .into_iter()
+            .filter(|iface| iface.name == watched.name)
+            .map(|iface| iface.ip())
+            .collect::<Vec<_>>();
+        assert!(peer.addrs().contains(&(watched.ip(), 8004)));
+        assert!(peer
+            .addrs()
+            .iter()
+            .all(|(ip, port)| *port == 8004 && watched_ips.contains(ip)));
+    }
+
+    #[tokio::test]
+    async fn test_port_on_all_interfaces() {
+        use tokio_stream::StreamExt;
+
+        let handle = tokio::runtime::Handle::current();
+
+        let _guard1 = Discoverer::new("test_all_addrs".to_string(), "all_peer1".to_string())
+            .with_port_on_all_interfaces(8005)
+            .with_local_addr_filter(LocalAddrFilter::default().with_loopback(true))
+            .with_cadence(Duration::from_secs(1))
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer1");
+
+        let guard2 = Discoverer::new("test_all_addrs".to_string(), "all_peer2".to_string())
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer2");
+        let mut events = guard2.events();
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.next().await {
+                    Some(Ok(DiscoveryEvent::Discovered { peer_id, peer }))
+                        if peer_id == "all_peer1" =>
+                    {
+                        return peer
+                    }
+                    Some(_) => {}
+                    None => panic!("event stream ended"),
+                }
+            }
+        })
+        .await
+        .expect("Timeout waiting for discovery");
+        assert!(peer.addrs().iter().all(|(_, port)| *port == 8005));
+        assert!(peer
+            .addrs()
+            .contains(&(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8005)));
+    }
+
+    #[tokio::test]
+    async fn test_peer_table() {
+        use tokio_stream::StreamExt;
+
+        let handle = tokio::runtime::Handle::current();
+
+        let _guard1 = Discoverer::new("test_table".to_string(), "table_peer1".to_string())
+            .with_addrs(8002, vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))])
+            .with_cadence(Duration::from_secs(1))
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer1");
+
+        let guard2 = Discoverer::new("test_table".to_string(), "table_peer2".to_string())
+            .spawn(&handle)
+            .expect("Failed to spawn discoverer2");
+        let mut events = guard2.events();
+
+        assert!(guard2.peer("table_peer1").await.is_none());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                if matches!(event, Ok(DiscoveryEvent::Discovered { ref peer_id, .. }) if peer_id == "table_peer1")
+                {
+                    return;
+                }
+            }
+            panic!("event stream ended");
+        })
+        .await
+        .expect("Timeout waiting for discovery");
+
+        let peer = guard2.peer("table_peer1").await.expect("peer is known");
+        assert_eq!(
+            peer.addrs(),
+            &[(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8002)]
+        );
+        let peers = guard2.peers().await;
+        assert_eq!(
+            peers.get("table_peer1").map(|p| p.addrs()),
+            Some(peer.addrs())
+        );
+        assert!(!peers.contains_key("table_peer2"));
+    }
 }
@@ -0,0 +1,196 @@
+use hickory_proto::{
+    rr::{Label, Name},
+    ProtoError,
+};
+use std::borrow::Cow;
+
+/// TXT attribute carrying a peer ID that does not fit into an instance label.
+pub(crate) const PEER_ID_KEY: &str = "_id";
+/// TXT attribute carrying the signature of an announcement, reserved also without signing.
+pub(crate) const SIGNATURE_KEY: &str = "_sig";
+
+/// Label marking subtype names.
+const SUB: &str = "_sub";
+/// Maximum length of a DNS label, see RFC 1035 section 2.3.4.
+const MAX_LABEL: usize = 63;
+/// Length of the hash suffix of abbreviated labels, i.e. a dash and 16 hex digits.
+const HASH_SUFFIX: usize = 17;
+/// Longest part of a peer ID in a TXT string, which holds at most 255 bytes.
+const PEER_ID_PART: usize = 255 - PEER_ID_KEY.len() - 1;
+
+/// The text of the DNS label representing a peer ID or host name.
+///
+/// Labels are raw bytes, so dots and non-ASCII characters are carried unchanged, as RFC 6763
+/// section 4.3 allows for instance names. Text longer than a label is abbreviated to a prefix
+/// followed by a hash of the whole text; the full peer ID is then announced in the TXT
+/// attribute [PEER_ID_KEY]. Abbreviating a label yields the label itself.
+pub fn label_text(text: &str) -> Cow<'_, str> {
+    if text.len() <= MAX_LABEL {
+        return Cow::Borrowed(text);
+    }
+    let mut prefix = MAX_LABEL - HASH_SUFFIX;
+    while !text.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    Cow::Owned(format!(
+        "{}-{:016x}",
+        &text[..prefix],
+        fnv1a(text.as_bytes())
+    ))
+}
+
+/// Whether a peer ID is abbreviated in its instance label and must be announced in the TXT record.
+pub fn is_abbreviated(peer_id: &str) -> bool {
+    peer_id.len() > MAX_LABEL
+}
+
+/// The TXT strings announcing an abbreviated peer ID in the attribute [PEER_ID_KEY].
+///
+/// Peer IDs too long for a single TXT string are split into several, joined in order when
+/// received.
+pub fn peer_id_parts(peer_id: &str) -> impl Iterator<Item = Vec<u8>> + '_ {
+    peer_id.as_bytes().chunks(PEER_ID_PART).map(|part| {
+        let mut string = format!("{PEER_ID_KEY}=").into_bytes();
+        string.extend_from_slice(part);
+        string
+    })
+}
+
+/// The instance name `PEER_ID._NAME._udp.local.` of a peer.
+pub fn instance_name(peer_id: &str, service_name: &Name) -> Result<Name, ProtoError> {
+    service_name.prepend_label(label(peer_id)?)
+}
+
+/// The host name `PEER_ID-PORT.local.` targeted by the SRV record of a port.
+pub fn host_name(peer_id: &str, port: u16) -> Result<Name, ProtoError> {
+    Name::from_ascii("local.")?.prepend_label(label(&format!("{peer_id}-{port}"))?)
+}
+
+/// The label for the given text, see [label_text].
+pub fn label(text: &str) -> Result<Label, ProtoError> {
+    Label::from_raw_bytes(label_text(text).as_bytes())
+}
+
+/// The name `_SUBTYPE._sub._NAME._udp.local.` browsed for a subtype, see RFC 6763 section 7.1.
+pub fn subtype_name(subtype: &str, service_name: &Name) -> Result<Name, ProtoError> {
+    service_name
+        .prepend_label(SUB)?
+        .prepend_label(subtype_label(subtype)?)
+}
+
+/// The label `_SUBTYPE` of a subtype name.
+pub fn subtype_label(subtype: &str) -> Result<Label, ProtoError> {
+    Label::from_raw_bytes(format!("_{subtype}").as_bytes())
+}
+
+/// The subtype named by `_SUBTYPE._sub._NAME._udp.local.`, if it is one of the given service.
+pub fn subtype_of<'a>(name: &'a Name, service_name: &Name) -> Option<&'a str> {
+    let mut labels = name.iter();
+    let subtype = labels.next()?.strip_prefix(b"_")?;
+    if !labels.next()?.eq_ignore_ascii_case(SUB.as_bytes())
+        || name.base_name().base_name() != *service_name
+    {
+        return None;
+    }
+    std::str::from_utf8(subtype).ok()
+}
+
+/// The text of the first label of a name, e.g. the instance label of an instance name.
+pub fn first_label(name: &Name) -> Option<&str> {
+    std::str::from_utf8(name.iter().next()?).ok()
+}
+
+/// 64-bit FNV-1a, a hash that is stable across platforms and releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn labels_are_abbreviated_beyond_63_bytes() {
+        let fits = "x".repeat(MAX_LABEL);
+        assert!(!is_abbreviated(&fits));
+        assert_eq!(label_text(&fits), fits);
+
+        let long = "x".repeat(MAX_LABEL + 1);
+        assert!(is_abbreviated(&long));
+        let text = label_text(&long);
+        assert_eq!(text.len(), MAX_LABEL);
+        assert!(text.starts_with(&"x".repeat(MAX_LABEL - HASH_SUFFIX)));
+        assert_ne!(text, label_text(&format!("{long}y")));
+        // abbreviations are labels themselves
+        assert_eq!(label_text(&text), text);
+        assert!(label(&long).is_ok());
+    }
+
+    #[test]
+    fn abbreviations_end_at_a_char_boundary() {
+        // 'ö' takes two bytes, so the prefix of 46 bytes would split the 24th
+        let long = "ö".repeat(40);
+        let text = label_text(&long);
+        assert!(text.starts_with(&"ö".repeat(23)));
+        assert_eq!(text.len(), 2 * 23 + HASH_SUFFIX);
+    }
+
+    #[test]
+    fn peer_ids_are_split_into_txt_strings() {
+        let join = |peer_id: &str| {
+            let parts = peer_id_parts(peer_id).collect::<Vec<_>>();
+            assert!(parts.iter().all(|part| part.len() <= 255));
+            let mut joined = Vec::new();
+            for part in &parts {
+                joined.extend_from_slice(part.strip_prefix(b"_id=").unwrap());
+            }
+            assert_eq!(joined, peer_id.as_bytes());
+            parts.len()
+        };
+        assert_eq!(join(&"x".repeat(PEER_ID_PART)), 1);
+        assert_eq!(
+            peer_id_parts(&"x".repeat(PEER_ID_PART))
+                .next()
+                .unwrap()
+                .len(),
+            255
+        );
+        assert_eq!(join(&"x".repeat(PEER_ID_PART + 1)), 2);
+        assert_eq!(join(&"x".repeat(1000)), 4);
+        // parts are bytes, joined before decoding UTF-8
+        assert_eq!(join(&"ö".repeat(150)), 2);
+    }
+
+    #[test]
+    fn instance_and_host_names() {
+        let service = Name::from_str("_swarm._udp.local.").unwrap();
+        let instance = instance_name("a.b", &service).unwrap();
+        assert_eq!(instance.num_labels(), 4);
+        assert_eq!(first_label(&instance), Some("a.b"));
+        assert_eq!(instance.base_name(), service);
+        let host = host_name("a.b", 1234).unwrap();
+        assert_eq!(first_label(&host), Some("a.b-1234"));
+        assert_eq!(host.base_name(), Name::from_str("local.").unwrap());
+    }
+
+    #[test]
+    fn subtypes_are_recognized_case_insensitively() {
+        let service = Name::from_str("_swarm._udp.local.").unwrap();
+        let name = subtype_name("worker", &service).unwrap();
+        assert_eq!(
+            name,
+            Name::from_str("_worker._sub._swarm._udp.local.").unwrap()
+        );
+        assert_eq!(subtype_of(&name, &service), Some("worker"));
+        let upper = Name::from_str("_Worker._SUB._swarm._udp.local.").unwrap();
+        assert_eq!(subtype_of(&upper, &service), Some("Worker"));
+        let other = Name::from_str("_worker._sub._other._udp.local.").unwrap();
+        assert_eq!(subtype_of(&other, &service), None);
+        assert_eq!(subtype_of(&service, &service), None);
+        assert!(subtype_label(&"x".repeat(MAX_LABEL - 1)).is_ok());
+        assert!(subtype_label(&"x".repeat(MAX_LABEL)).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use hickory_proto::{
+    op::{Message, MessageType},
+    rr::{Name, Record},
+    serialize::binary::BinEncodable,
+};
+
+/// Default maximum size of sent packets, fitting into a 1500 byte Ethernet frame also with IPv6.
+pub const DEFAULT_PACKET_SIZE: usize = 1452;
+/// Smallest configurable packet size, see RFC 6762 section 17.
+pub const MIN_PACKET_SIZE: usize = 512;
+/// Largest packet size allowed by RFC 6762 section 17, which limits packets to 9000 bytes
+/// including IPv6 and UDP headers.
+pub const MAX_PACKET_SIZE: usize = 8952;
+/// Size of the receive buffer, large enough for any mDNS packet also over IPv4.
+pub const RECEIVE_BUFFER: usize = 9000;
+
+const HEADER_SIZE: usize = 12;
+
+/// Space for the data of the TXT record, which cannot be split across packets.
+///
+/// The record must fit into a packet together with the header, its owner name and the
+/// `reserved` bytes, e.g. for the authentication by a swarm secret.
+pub fn txt_budget(max_size: usize, instance: &Name, reserved: usize) -> usize {
+    // type, class, TTL and data length
+    const FIXED: usize = 10;
+    max_size.saturating_sub(HEADER_SIZE + instance.len() + 1 + FIXED + reserved)
+}
+
+type AddRecord = fn(&mut Message, Record) -> &mut Message;
+
+/// Split a message into packets of at most `max_size` bytes, see RFC 6762 section 17.
+///
+/// Questions stay in the first packet, records are distributed in order. When a query is split,
+/// all packets but the last have the TC bit set to announce more known answers, see RFC 6762
+/// section 7.2. Split responses need no marker, their records are cached by the receiver.
+///
+/// Record sizes are estimated without name compression, checking the actual size of a packet
+/// before starting the next one. A record that is too large by itself is sent in a packet of its
+/// own.
+pub fn split(msg: &Message, max_size: usize) -> Vec<Message> {
+    let size = |bytes: Result<Vec<u8>, _>| bytes.map(|b| b.len()).unwrap_or(0);
+    let questions = msg
+        .queries
+        .iter()
+        .map(|query| size(query.to_bytes()))
+        .sum::<usize>();
+    let records = [&msg.answers, &msg.authorities, &msg.additionals]
+        .into_iter()
+        .flatten()
+        .map(|record| size(record.to_bytes()))
+        .sum::<usize>();
+    if HEADER_SIZE + questions + records <= max_size || encoded_len(msg) <= max_size {
+        return vec![msg.clone()];
+    }
+
+    let empty = || {
+        let mut packet = Message::new(
+            msg.metadata.id,
+            msg.metadata.message_type,
+            msg.metadata.op_code,
+        );
+        packet.metadata.authoritative = msg.metadata.authoritative;
+        packet
+    };
+    let mut packet = empty();
+    packet.add_queries(msg.queries.iter().cloned());
+    let mut packet_size = HEADER_SIZE + questions;
+    let mut packets = Vec::new();
+
+    let sections: [(&[Record], AddRecord); 3] = [
+        (&msg.answers, Message::add_answer),
+        (&msg.authorities, Message::add_authority),
+        (&msg.additionals, Message::add_additional),
+    ];
+    for (records, add) in sections {
+        for record in records {
+            let record_size = size(record.to_bytes());
+            let is_empty = packet.queries.is_empty()
+                && packet.answers.is_empty()
+                && packet.authorities.is_empty()
+                && packet.additionals.is_empty();
+            if packet_size + record_size > max_size && !is_empty {
+                // the estimate ignores name compression, so check the actual size
+                packet_size = encoded_len(&packet);
+                if packet_size + record_size > max_size {
+                    packets.push(std::mem::replace(&mut packet, empty()));
+                    packet_size = HEADER_SIZE;
+                }
+            }
+            add(&mut packet, record.clone());
+            packet_size += record_size;
+        }
+    }
+    packets.push(packet);
+
+    if msg.metadata.message_type == MessageType::Query {
+        let last = packets.len() - 1;
+        for packet in &mut packets[..last] {
+            packet.metadata.truncation = true;
+        }
+    }
+    packets
+}
+
+pub fn encoded_len(msg: &Message) -> usize {
+    msg.to_vec().map(|bytes| bytes.len()).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::{
+        op::{OpCode, Query},
+        rr::{
+            rdata::{PTR, TXT},
+            RData, RecordType,
+        },
+    };
+    use std::str::FromStr;
+
+    /// A TXT record whose data takes exactly `len` bytes, in strings of at most 255 bytes.
+    fn txt(name: &str, len: usize) -> Record {
+        let mut strings = Vec::new();
+        let mut left = len;
+        while left > 0 {
+            let string = left.min(256) - 1;
+            strings.push("x".repeat(string));
+            left -= 1 + string;
+        }
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            120,
+            RData::TXT(TXT::new(strings)),
+        )
+    }
+
+    fn response(records: impl IntoIterator<Item = Record>) -> Message {
+        let mut msg = Message::new(0, MessageType::Response, OpCode::Query);
+        msg.add_answers(records);
+        msg
+    }
+
+    #[test]
+    fn txt_budget_fills_a_packet_exactly() {
+        let instance = Name::from_str("peer._swarm._udp.local.").unwrap();
+        for reserved in [0, 100] {
+            let budget = txt_budget(DEFAULT_PACKET_SIZE, &instance, reserved);
+            let msg = response([txt("peer._swarm._udp.local.", budget)]);
+            assert_eq!(encoded_len(&msg) + reserved, DEFAULT_PACKET_SIZE);
+        }
+        assert_eq!(txt_budget(MIN_PACKET_SIZE, &instance, MIN_PACKET_SIZE), 0);
+    }
+
+    #[test]
+    fn packets_up_to_the_maximum_are_not_split() {
+        // header and two records with uncompressible names of 3 bytes and 10 fixed bytes each
+        let msg = response([txt("a.", 700), txt("b.", 714)]);
+        assert_eq!(encoded_len(&msg), DEFAULT_PACKET_SIZE);
+        assert_eq!(split(&msg, DEFAULT_PACKET_SIZE), [msg]);
+
+        let msg = response([txt("a.", 700), txt("b.", 715)]);
+        let packets = split(&msg, DEFAULT_PACKET_SIZE);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].answers, msg.answers[..1]);
+        assert_eq!(packets[1].answers, msg.answers[1..]);
+        assert!(packets.iter().all(|p| !p.metadata.truncation));
+    }
+
+    #[test]
+    fn split_queries_announce_more_known_answers() {
+        let service = Name::from_str("_swarm._udp.local.").unwrap();
+        let mut msg = Message::new(0, MessageType::Query, OpCode::Query);
+        msg.add_query(Query::query(service.clone(), RecordType::PTR));
+        for i in 0..100 {
+            let instance = Name::from_str(&format!("peer{i:03}._swarm._udp.local.")).unwrap();
+            msg.add_answer(Record::from_rdata(
+                service.clone(),
+                120,
+                RData::PTR(PTR(instance)),
+            ));
+        }
+        let packets = split(&msg, MIN_PACKET_SIZE);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| encoded_len(p) <= MIN_PACKET_SIZE));
+        assert_eq!(packets[0].queries, msg.queries);
+        assert!(packets[1..].iter().all(|p| p.queries.is_empty()));
+        let (last, rest) = packets.split_last().unwrap();
+        assert!(rest.iter().all(|p| p.metadata.truncation));
+        assert!(!last.metadata.truncation);
+        let answers = packets.iter().flat_map(|p| p.answers.clone());
+        assert!(answers.eq(msg.answers.iter().cloned()));
+    }
+
+    #[test]
+    fn oversized_records_are_sent_alone() {
+        let msg = response([txt("a.", 10), txt("b.", 2000), txt("c.", 10)]);
+        let packets = split(&msg, DEFAULT_PACKET_SIZE);
+        assert_eq!(packets.len(), 3);
+        for (packet, record) in packets.iter().zip(&msg.answers) {
+            assert_eq!(packet.answers, std::slice::from_ref(record));
+        }
+    }
+}
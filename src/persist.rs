@@ -0,0 +1,235 @@
+use crate::{Peer, TxtData};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+/// Interval at which the peer table is written to the cache file.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+/// Format version of the cache file, files of other versions are ignored.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    peers: Vec<SavedPeer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPeer {
+    peer_id: String,
+    addrs: Vec<(IpAddr, u16)>,
+    txt: TxtData,
+    /// milliseconds since the Unix epoch
+    last_seen: u64,
+    /// seconds
+    ttl: u64,
+}
+
+/// The file in which the peer table of a service is kept across restarts.
+pub struct PeerCache {
+    path: PathBuf,
+    saved: Instant,
+}
+
+impl PeerCache {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved: Instant::now(),
+        }
+    }
+
+    /// Read the peers from the file, dropping those that would have expired in the meantime.
+    ///
+    /// Like in the peer table, this happens after their TTL or the grace period, which is
+    /// computed for the number of saved peers.
+    pub async fn load(&self, grace: impl FnOnce(usize) -> Duration) -> BTreeMap<String, Peer> {
+        let path = self.path.clone();
+        let bytes = match tokio::task::spawn_blocking(move || std::fs::read(path)).await {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+            Ok(Err(e)) => {
+                tracing::warn!("cannot read peer cache {}: {}", self.path.display(), e);
+                return BTreeMap::new();
+            }
+            Err(_) => return BTreeMap::new(),
+        };
+        let snapshot = match bincode::deserialize::<Snapshot>(&bytes) {
+            Ok(snapshot) if snapshot.version == VERSION => snapshot,
+            Ok(_) => {
+                tracing::info!(
+                    "ignoring peer cache {} of other version",
+                    self.path.display()
+                );
+                return BTreeMap::new();
+            }
+            Err(e) => {
+                tracing::warn!("cannot decode peer cache {}: {}", self.path.display(), e);
+                return BTreeMap::new();
+            }
+        };
+
+        let now = Instant::now();
+        let unix_now = unix_millis(SystemTime::now());
+        let grace = grace(snapshot.peers.len());
+        let mut peers = BTreeMap::new();
+        for saved in snapshot.peers {
+            let age = Duration::from_millis(unix_now.saturating_sub(saved.last_seen));
+            let ttl = Duration::from_secs(saved.ttl);
+            // peers of older versions announce no TTL and are only subject to the grace period
+            let fresh = age < grace && (ttl.is_zero() || age < ttl);
+            if !fresh {
+                continue;
+            }
+            let last_seen = seen_before(now, age);
+            let mut peer = Peer::new();
+            peer.addrs = saved.addrs;
+            peer.txt = saved.txt;
+            peer.last_seen = last_seen;
+            peer.ttl = ttl;
+            peers.insert(saved.peer_id, peer);
+        }
+        tracing::debug!("loaded {} peers from {}", peers.len(), self.path.display());
+        peers
+    }
+
+    /// Write the peers to the file if the last snapshot is older than a minute.
+    pub async fn save_periodically<'a>(
+        &mut self,
+        peers: impl Iterator<Item = (&'a String, &'a Peer)>,
+    ) {
+        if self.saved.elapsed() >= SNAPSHOT_INTERVAL {
+            self.save(peers).await;
+        }
+    }
+
+    /// Write the peers to the file, replacing it atomically.
+    pub async fn save<'a>(&mut self, peers: impl Iterator<Item = (&'a String, &'a Peer)>) {
+        self.saved = Instant::now();
+        let unix_now = unix_millis(SystemTime::now());
+        let snapshot = Snapshot {
+            version: VERSION,
+            peers: peers
+                .map(|(peer_id, peer)| SavedPeer {
+                    peer_id: peer_id.clone(),
+                    addrs: peer.addrs.clone(),
+                    txt: peer.txt.clone(),
+                    last_seen: unix_now.saturating_sub(peer.last_seen.elapsed().as_millis() as u64),
+                    ttl: peer.ttl.as_secs(),
+                })
+                .collect(),
+        };
+        let bytes = match bincode::serialize(&snapshot) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!("cannot encode peer cache: {}", e);
+                return;
+            }
+        };
+        let path = self.path.clone();
+        let written = tokio::task::spawn_blocking(move || write(&path, &bytes)).await;
+        if let Ok(Err(e)) = written {
+            tracing::warn!("cannot write peer cache {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Write to a temporary file first, so that a crash does not leave a truncated cache behind.
+fn write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// The instant `age` before `now`, or `now` if the monotonic clock does not reach back that far.
+///
+/// Depending on the platform, this is the case for ages beyond the uptime of the system, so that
+/// the peer counts as seen at startup.
+fn seen_before(now: Instant, age: Duration) -> Instant {
+    now.checked_sub(age).unwrap_or(now)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn saved(peer_id: &str, age: Duration, ttl: Duration) -> SavedPeer {
+        let now = unix_millis(SystemTime::now());
+        SavedPeer {
+            peer_id: peer_id.to_owned(),
+            addrs: vec![([10, 0, 0, 1].into(), 1234)],
+            txt: Default::default(),
+            last_seen: now.saturating_sub(age.as_millis() as u64),
+            ttl: ttl.as_secs(),
+        }
+    }
+
+    async fn load(name: &str, peers: Vec<SavedPeer>, grace: Duration) -> BTreeMap<String, Peer> {
+        let path = std::env::temp_dir().join(format!(
+            "swarm-discovery-{}-{name}.peers",
+            std::process::id()
+        ));
+        let snapshot = Snapshot {
+            version: VERSION,
+            peers,
+        };
+        write(&path, &bincode::serialize(&snapshot).unwrap()).unwrap();
+        let peers = PeerCache::new(path.clone()).load(|_| grace).await;
+        let _ = std::fs::remove_file(&path);
+        peers
+    }
+
+    #[tokio::test]
+    async fn expired_peers_are_not_loaded() {
+        let peers = load(
+            "expired",
+            vec![
+                saved("fresh", Duration::from_secs(10), Duration::from_secs(120)),
+                saved(
+                    "past-ttl",
+                    Duration::from_secs(130),
+                    Duration::from_secs(120),
+                ),
+                saved("past-grace", 2 * HOUR, 3 * HOUR),
+                saved("old-version", Duration::from_secs(10), Duration::ZERO),
+                saved("old-version-past-grace", 2 * HOUR, Duration::ZERO),
+            ],
+            HOUR,
+        )
+        .await;
+        assert_eq!(peers.keys().collect::<Vec<_>>(), ["fresh", "old-version"]);
+        let age = peers["fresh"].age();
+        assert!(
+            age >= Duration::from_secs(10) && age < Duration::from_secs(11),
+            "{age:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn peers_older_than_the_monotonic_clock_are_loaded() {
+        // the Unix epoch lies before the start of any monotonic clock
+        let mut peer = saved("ancient", Duration::ZERO, Duration::MAX);
+        peer.last_seen = 0;
+        peer.ttl = u64::MAX;
+        let peers = load("ancient", vec![peer], Duration::MAX).await;
+        assert_eq!(peers["ancient"].addrs().len(), 1);
+
+        let now = Instant::now();
+        assert_eq!(seen_before(now, Duration::MAX), now);
+        assert_eq!(seen_before(now, HOUR), now - HOUR);
+    }
+}
@@ -1,12 +1,27 @@
-use crate::{sender::MdnsMsg, Peer, TxtData};
+use crate::{
+    cache::RecordCache,
+    names,
+    packets::RECEIVE_BUFFER,
+    sender::{Known, KnownAnswers, MdnsMsg, ENUMERATION_TTL, SERVICES},
+    socket::Mode,
+    stats::{Counter, Counters},
+    transport::Receive,
+    Peer, TxtData,
+};
 use acto::{ActoCell, ActoRef, ActoRuntime};
 use hickory_proto::{
-    op::Message,
-    rr::{DNSClass, Name, RData, RecordType},
+    op::{Message, MessageType},
+    rr::{DNSClass, Name, RData, Record, RecordType},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use std::{collections::BTreeMap, net::IpAddr, str::FromStr, sync::Arc, time::Instant};
 use thiserror::Error;
-use tokio::net::UdpSocket;
+use tokio::time::Instant;
 
 /// Errors that can occur when receiving on the socket.
 #[derive(Debug, Error)]
@@ -16,33 +31,77 @@ pub struct ReceiverError {
     source: std::io::Error,
 }
 
+/// A service served by a receiver.
+pub struct Dispatch {
+    pub service_name: Name,
+    #[cfg(feature = "signing")]
+    pub verification: crate::Verification,
+    #[cfg(feature = "swarm-secret")]
+    pub secret: Option<crate::secret::SwarmSecret>,
+    pub target: ActoRef<MdnsMsg>,
+    /// Records of incomplete announcements, shared by all receivers.
+    pub cache: Mutex<RecordCache>,
+    pub stats: Arc<Counters>,
+}
+
+/// The services served by a receiver.
+pub type Services = Arc<[Dispatch]>;
+
 pub async fn receiver(
     _ctx: ActoCell<(), impl ActoRuntime>,
-    service_name: Name,
-    socket: Arc<UdpSocket>,
-    target: ActoRef<MdnsMsg>,
+    services: Services,
+    socket: Arc<dyn Receive>,
 ) -> Result<(), ReceiverError> {
-    let mut buf = [0; 1472];
+    let mut buf = vec![0; RECEIVE_BUFFER];
     loop {
         let (len, addr) = socket
             .recv_from(&mut buf)
             .await
             .map_err(ReceiverError::from)?;
-        let msg = &buf[..len];
         tracing::trace!("received {} bytes from {}", len, addr);
-        if let Some(msg) = handle_msg(msg, &service_name, addr.ip()) {
-            target.send(msg);
+        let packet = match Message::from_vec(&buf[..len]) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::debug!("error parsing mDNS packet: {}", e);
+                continue;
+            }
+        };
+        for service in services.iter() {
+            // checked first so that records of outsiders do not enter the cache
+            #[cfg(feature = "swarm-secret")]
+            if let Some(secret) = &service.secret {
+                if !secret.verify(&packet, &service.service_name) {
+                    tracing::trace!("ignoring packet from {} not holding the secret", addr);
+                    continue;
+                }
+            }
+            let withdrawn = withdrawn_subtypes(&packet, &service.service_name);
+            let msg = handle_msg(&packet, service, addr.ip());
+            if !withdrawn.is_empty() || msg.is_some() {
+                service.stats.add(Counter::BytesReceived, len as u64);
+            }
+            // sent first, so that the records of the same packet do not keep the subtypes
+            if !withdrawn.is_empty() {
+                service.target.send(MdnsMsg::Withdrawn(withdrawn));
+            }
+            let Some(msg) = msg else {
+                continue;
+            };
+            #[cfg(feature = "signing")]
+            let Some(msg) = verify_msg(msg, service) else {
+                continue;
+            };
+            service.target.send(msg);
         }
     }
 }
 
-fn handle_msg(buf: &[u8], service_name: &Name, addr: IpAddr) -> Option<MdnsMsg> {
-    let packet = match Message::from_vec(buf) {
-        Ok(p) => p,
-        Err(e) => {
-            tracing::debug!("error parsing mDNS packet: {}", e);
-            return None;
-        }
+fn handle_msg(packet: &Message, service: &Dispatch, addr: IpAddr) -> Option<MdnsMsg> {
+    let service_name = &service.service_name;
+    let services = Name::from_str(SERVICES).unwrap();
+    let mode = match addr {
+        IpAddr::V4(_) => Mode::V4,
+        IpAddr::V6(_) => Mode::V6,
     };
     for question in &packet.queries {
         if question.query_class() != DNSClass::IN {
@@ -52,109 +111,243 @@ fn handle_msg(buf: &[u8], service_name: &Name, addr: IpAddr) -> Option<MdnsMsg>
             );
             continue;
         }
-        if question.query_type() != RecordType::PTR {
-            tracing::trace!(
-                "received mDNS query with wrong type {}",
-                question.query_type()
-            );
-            continue;
+        let name = question.name();
+        match question.query_type() {
+            RecordType::ANY
+                if name.base_name() == *service_name && !packet.authorities.is_empty() =>
+            {
+                // probe for an instance name, see RFC 6762 section 8.1
+                tracing::debug!("received mDNS probe for {}", name);
+                let claims = parse_peers(packet.authorities.iter(), service_name);
+                if claims.is_empty() {
+                    continue;
+                }
+                return Some(MdnsMsg::Probe(claims));
+            }
+            RecordType::SRV | RecordType::TXT | RecordType::ANY
+                if name.base_name() == *service_name =>
+            {
+                // resolving a single instance, e.g. `dns-sd -L`
+                let Some(peer_id) = names::first_label(name) else {
+                    continue;
+                };
+                tracing::debug!("received mDNS query for instance {}", name);
+                return Some(MdnsMsg::Resolve(peer_id.to_owned(), mode));
+            }
+            RecordType::PTR
+                if *name == *service_name || names::subtype_of(name, service_name).is_some() =>
+            {
+                tracing::debug!("received mDNS query for {}", name);
+                let known = Known {
+                    source: Some(addr),
+                    answers: known_answers(packet, service_name),
+                    truncated: packet.metadata.truncation,
+                    subtype: names::subtype_of(name, service_name).map(ToOwned::to_owned),
+                };
+                return Some(match addr {
+                    IpAddr::V4(_) => MdnsMsg::QueryV4(known),
+                    IpAddr::V6(_) => MdnsMsg::QueryV6(known),
+                });
+            }
+            RecordType::PTR if *name == services => {
+                // service type enumeration, see RFC 6763 section 9
+                if is_enumeration_known(packet, service_name) {
+                    tracing::trace!("service type {} already known to querier", service_name);
+                    continue;
+                }
+                tracing::debug!("received mDNS service type enumeration query");
+                return Some(MdnsMsg::Enumerate(mode));
+            }
+            query_type => {
+                tracing::trace!(
+                    "received mDNS query for other record {} {}",
+                    name,
+                    query_type
+                );
+            }
         }
-        if question.name() != service_name {
-            tracing::trace!("received mDNS query for wrong service {}", question.name());
-            continue;
+    }
+
+    if packet.metadata.message_type == MessageType::Query {
+        if !packet.queries.is_empty() {
+            return None;
         }
-        tracing::debug!("received mDNS query for {}", question.name());
-        return Some(match addr {
-            IpAddr::V4(_) => MdnsMsg::QueryV4,
-            IpAddr::V6(_) => MdnsMsg::QueryV6,
+        // continuation of a truncated query, see RFC 6762 section 7.2
+        let answers = known_answers(packet, service_name);
+        return (!answers.is_empty()).then(|| {
+            MdnsMsg::MoreKnown(Known {
+                source: Some(addr),
+                answers,
+                truncated: packet.metadata.truncation,
+                subtype: None,
+            })
         });
     }
 
-    let local = Name::from_str("local.").unwrap();
+    let records = packet.answers.iter().chain(&packet.additionals);
+    // addresses are only of interest for the hosts of our service's instances
+    let targets = records
+        .clone()
+        .filter_map(|record| match &record.data {
+            RData::SRV(srv) if record.name.base_name() == *service_name => Some(&srv.target),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let mut cache = service.cache.lock().unwrap();
+    let fresh = records
+        .filter(|record| match record.record_type() {
+            RecordType::SRV | RecordType::TXT => record.name.base_name() == *service_name,
+            RecordType::A | RecordType::AAAA => {
+                targets.contains(&record.name) || cache.is_target(&record.name)
+            }
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    if fresh.is_empty() {
+        return None;
+    }
+    let records = cache.merge(fresh.iter().copied(), addr);
+    drop(cache);
+    let mut ret = parse_peers(records.iter(), service_name);
+    // only report peers this packet has news about
+    let fresh_names = fresh
+        .iter()
+        .map(|record| &record.name)
+        .collect::<BTreeSet<_>>();
+    let mut touched = BTreeSet::new();
+    for record in &records {
+        let RData::SRV(srv) = &record.data else {
+            continue;
+        };
+        if fresh_names.contains(&record.name) || fresh_names.contains(&srv.target) {
+            touched.extend(names::first_label(&record.name));
+        }
+    }
+    ret.retain(|peer_id, _| touched.contains(&*names::label_text(peer_id)));
+    // subtypes are announced along with the other records, see RFC 6763 section 7.1
+    let mut subtypes = BTreeMap::<&str, BTreeSet<String>>::new();
+    for record in &packet.answers {
+        let RData::PTR(ptr) = &record.data else {
+            continue;
+        };
+        let Some(subtype) = names::subtype_of(&record.name, service_name) else {
+            continue;
+        };
+        // goodbyes are handled by withdrawn_subtypes
+        if record.ttl == 0 || ptr.0.base_name() != *service_name {
+            continue;
+        }
+        if let Some(label) = names::first_label(&ptr.0) {
+            subtypes
+                .entry(label)
+                .or_default()
+                .insert(subtype.to_ascii_lowercase());
+        }
+    }
+    for (peer_id, peer) in &mut ret {
+        if let Some(subtypes) = subtypes.remove(&*names::label_text(peer_id)) {
+            peer.subtypes = subtypes;
+        }
+    }
+    // most likely a packet for another service
+    (!ret.is_empty()).then_some(MdnsMsg::Response(ret))
+}
 
+/// Extract the announced peers from SRV and TXT records and their associated addresses.
+///
+/// Records are accepted in any section, as DNS-SD places SRV and TXT records in the additional
+/// section when answering PTR queries. Peers are keyed by their instance label, or by the full
+/// peer ID from the TXT attribute [names::PEER_ID_KEY] if the label is its abbreviation.
+fn parse_peers<'a>(
+    records: impl Iterator<Item = &'a Record> + Clone,
+    service_name: &Name,
+) -> BTreeMap<String, Peer> {
     let mut peer_ports: BTreeMap<Name, Vec<(u16, String)>> = BTreeMap::new();
     let mut peer_txt: BTreeMap<String, TxtData> = BTreeMap::new();
-    for response in &packet.answers {
-        if response.dns_class != DNSClass::IN {
+    let mut peer_ttl: BTreeMap<String, u32> = BTreeMap::new();
+    let mut full_ids: BTreeMap<String, String> = BTreeMap::new();
+    for record in records.clone() {
+        if !matches!(record.data, RData::SRV(_) | RData::TXT(_)) {
+            continue;
+        }
+        if record.dns_class != DNSClass::IN {
             tracing::trace!(
-                "received mDNS response with wrong class {:?}",
-                response.dns_class
+                "received mDNS record with wrong class {:?}",
+                record.dns_class
             );
             continue;
         }
-        let name = &response.name;
+        let name = &record.name;
         if name.base_name() != *service_name {
-            tracing::trace!("received mDNS response with wrong service {}", name);
+            tracing::trace!("received mDNS record for wrong service {}", name);
             continue;
         }
-        tracing::debug!("received mDNS response for {}", name);
-        let Some(peer_id_bytes) = name.iter().next() else {
-            continue;
-        };
-        let Ok(peer_id) = std::str::from_utf8(peer_id_bytes) else {
-            tracing::debug!(
-                "received mDNS response with invalid peer ID {:?}",
-                peer_id_bytes
-            );
+        tracing::debug!("received mDNS record for {}", name);
+        let Some(peer_id) = names::first_label(name) else {
+            tracing::debug!("received mDNS record with invalid peer ID {}", name);
             continue;
         };
-        match &response.data {
+        match &record.data {
             RData::SRV(srv) => {
+                peer_ttl
+                    .entry(peer_id.to_string())
+                    .and_modify(|ttl| *ttl = (*ttl).min(record.ttl))
+                    .or_insert(record.ttl);
                 peer_ports
                     .entry(srv.target.clone())
                     .or_default()
                     .push((srv.port, peer_id.to_string()));
             }
             RData::TXT(txt) => {
+                // the full peer ID may be split across several strings
+                let mut full_id = Vec::new();
                 for s in txt.txt_data.iter() {
-                    let Ok(s) = std::str::from_utf8(s) else {
+                    // values may be arbitrary bytes, only the key must be UTF-8
+                    let (key, value) = match s.iter().position(|b| *b == b'=') {
+                        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+                        None => (&s[..], None),
+                    };
+                    let Ok(key) = std::str::from_utf8(key) else {
                         continue;
                     };
-                    if s.is_empty() {
+                    if key.is_empty() {
+                        continue;
+                    }
+                    if key == names::PEER_ID_KEY {
+                        full_id.extend_from_slice(value.unwrap_or_default());
                         continue;
                     }
-                    let (key, value) = match s.split_once('=') {
-                        Some((key, value)) => (key, Some(value)),
-                        None => (s, None),
-                    };
                     let map = peer_txt.entry(peer_id.to_string()).or_default();
                     if !map.contains_key(key) {
-                        map.insert(key.to_string(), value.map(ToString::to_string));
+                        map.insert(key.to_string(), value.map(<[u8]>::to_vec));
                     }
                 }
+                let full_id = String::from_utf8(full_id).ok();
+                if let Some(full_id) = full_id.filter(|id| names::label_text(id) == peer_id) {
+                    full_ids.insert(peer_id.to_owned(), full_id);
+                }
             }
-            _ => {
-                tracing::trace!("received mDNS response with wrong data {:?}", response.data);
-            }
+            _ => {}
         }
     }
 
     let mut peer_addrs: BTreeMap<String, Vec<(IpAddr, u16)>> = BTreeMap::new();
-    for additional in &packet.additionals {
-        if additional.dns_class != DNSClass::IN {
+    for record in records {
+        let ip: IpAddr = match &record.data {
+            RData::A(a) => a.0.into(),
+            RData::AAAA(a) => a.0.into(),
+            _ => continue,
+        };
+        if record.dns_class != DNSClass::IN {
             tracing::trace!(
-                "received mDNS additional with wrong class {:?}",
-                additional.dns_class
+                "received mDNS address with wrong class {:?}",
+                record.dns_class
             );
             continue;
         }
-        let name = &additional.name;
-        if name.base_name() != local {
-            tracing::trace!("received mDNS additional for wrong service {}", name);
-            continue;
-        }
-        tracing::trace!("received mDNS additional for {}", name);
-        let ip: IpAddr = match &additional.data {
-            RData::A(a) => a.0.into(),
-            RData::AAAA(a) => a.0.into(),
-            _ => {
-                tracing::debug!(
-                    "received mDNS additional with wrong data {:?}",
-                    additional.data
-                );
-                continue;
-            }
-        };
+        // host names need not be directly under `local.`, they are found via SRV targets
+        let name = &record.name;
+        tracing::trace!("received mDNS address for {}", name);
         for (port, peer_id) in peer_ports.get(name).map(|x| &**x).unwrap_or(&[]) {
             peer_addrs
                 .entry(peer_id.clone())
@@ -168,12 +361,110 @@ fn handle_msg(buf: &[u8], service_name: &Name, addr: IpAddr) -> Option<MdnsMsg>
         addrs.dedup();
         let txt = peer_txt.remove(&peer_id).unwrap_or_default();
         let last_seen = Instant::now();
+        // a TTL of zero announces that the peer is going away, or comes from an older version
+        let ttl = Duration::from_secs(peer_ttl.get(&peer_id).copied().unwrap_or_default().into());
         let peer = Peer {
             addrs,
             last_seen,
             txt,
+            verified: false,
+            ttl,
+            subtypes: Default::default(),
         };
-        ret.insert(peer_id, peer);
+        ret.insert(full_ids.remove(&peer_id).unwrap_or(peer_id), peer);
+    }
+    ret
+}
+
+/// Collect the subtypes withdrawn by goodbye PTR records, keyed by instance label.
+///
+/// A peer removing a subtype sends a goodbye for its subtype PTR alone, without any records
+/// that would make the packet count as an announcement of the peer.
+fn withdrawn_subtypes(packet: &Message, service_name: &Name) -> BTreeMap<String, BTreeSet<String>> {
+    let mut withdrawn = BTreeMap::<String, BTreeSet<String>>::new();
+    if packet.metadata.message_type != MessageType::Response {
+        return withdrawn;
+    }
+    for record in &packet.answers {
+        let RData::PTR(ptr) = &record.data else {
+            continue;
+        };
+        let Some(subtype) = names::subtype_of(&record.name, service_name) else {
+            continue;
+        };
+        if record.ttl != 0 || ptr.0.base_name() != *service_name {
+            continue;
+        }
+        if let Some(label) = names::first_label(&ptr.0) {
+            withdrawn
+                .entry(label.to_owned())
+                .or_default()
+                .insert(subtype.to_ascii_lowercase());
+        }
+    }
+    withdrawn
+}
+
+/// Extract the instances listed as known answers in a query.
+fn known_answers(packet: &Message, service_name: &Name) -> KnownAnswers {
+    let mut known = KnownAnswers::new();
+    for answer in &packet.answers {
+        let RData::PTR(ptr) = &answer.data else {
+            continue;
+        };
+        let browsed =
+            answer.name == *service_name || names::subtype_of(&answer.name, service_name).is_some();
+        if !browsed || ptr.0.base_name() != *service_name {
+            continue;
+        }
+        let Some(label) = names::first_label(&ptr.0) else {
+            continue;
+        };
+        known.insert(label.to_owned(), answer.ttl);
+    }
+    known
+}
+
+/// Whether the querier lists our service type as known answer to the enumeration query.
+fn is_enumeration_known(packet: &Message, service_name: &Name) -> bool {
+    packet.answers.iter().any(|answer| {
+        matches!(&answer.data, RData::PTR(ptr) if ptr.0 == *service_name)
+            && answer.ttl >= ENUMERATION_TTL / 2
+    })
+}
+
+/// Check the signatures of the peers in a response, dropping those that are required but invalid.
+#[cfg(feature = "signing")]
+fn verify_msg(msg: MdnsMsg, service: &Dispatch) -> Option<MdnsMsg> {
+    use crate::{signing, Verification};
+
+    let MdnsMsg::Response(mut peers) = msg else {
+        return Some(msg);
+    };
+    match service.verification {
+        Verification::Off => {
+            for peer in peers.values_mut() {
+                peer.txt.remove(names::SIGNATURE_KEY);
+            }
+        }
+        Verification::Flag => {
+            for (peer_id, peer) in &mut peers {
+                peer.verified = signing::verify(&service.service_name, peer_id, peer);
+            }
+        }
+        Verification::Require => {
+            peers.retain(|peer_id, peer| {
+                peer.verified = signing::verify(&service.service_name, peer_id, peer);
+                if !peer.verified {
+                    tracing::debug!("ignoring unverified response from {}", peer_id);
+                }
+                peer.verified
+            });
+            if peers.is_empty() {
+                // anyone can send unverifiable responses, so they must not suppress our own
+                return None;
+            }
+        }
     }
-    Some(MdnsMsg::Response(ret))
+    Some(MdnsMsg::Response(peers))
 }
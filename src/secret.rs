@@ -0,0 +1,217 @@
+use data_encoding::BASE64_NOPAD;
+use hickory_proto::{
+    op::Message,
+    rr::{rdata::TXT, Name, RData, Record, RecordType},
+    serialize::binary::BinEncodable,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Domain separation for the authenticated content.
+const CONTEXT: &[u8] = b"swarm-discovery membership v1\0";
+
+/// Number of random bytes drawn for each sent packet.
+pub(crate) const NONCE_LEN: usize = 16;
+/// Time by which the timestamp of a received packet may differ from the local clock.
+///
+/// This tolerates some clock skew between peers; a recorded packet can only be replayed within
+/// this window, when its content is still about as current as that of fresh packets.
+const MAX_SKEW: Duration = Duration::from_secs(30);
+
+/// A secret shared by all members of a swarm.
+///
+/// Every sent packet carries an HMAC-SHA256 over its questions and records, a fresh random
+/// nonce and the time of sending, transmitted as a TXT record in the additional section whose
+/// name is the service name. Packets whose time differs from the local clock by more than
+/// [MAX_SKEW] are rejected.
+#[derive(Clone)]
+pub(crate) struct SwarmSecret {
+    secret: Arc<[u8]>,
+    clock: Clock,
+}
+
+impl fmt::Debug for SwarmSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SwarmSecret(..)")
+    }
+}
+
+/// The wall clock providing the timestamps of sent packets and checking those of received ones.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Clock {
+    System,
+    /// Starts at `wall` and advances with the Tokio clock from `at`, see
+    /// [MemoryNetwork](crate::test_util::MemoryNetwork).
+    #[cfg(feature = "test-util")]
+    Virtual {
+        wall: SystemTime,
+        at: tokio::time::Instant,
+    },
+}
+
+impl Clock {
+    /// Seconds since the UNIX epoch.
+    fn now(&self) -> u64 {
+        let now = match self {
+            Clock::System => SystemTime::now(),
+            #[cfg(feature = "test-util")]
+            Clock::Virtual { wall, at } => *wall + at.elapsed(),
+        };
+        now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+}
+
+impl SwarmSecret {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.into(),
+            clock: Clock::System,
+        }
+    }
+
+    /// Use the given clock for the timestamps of packets, i.e. that of the network in use.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Return a copy of `msg` that carries the authenticator.
+    ///
+    /// Returns `None` if the message cannot be encoded.
+    pub fn seal(
+        &self,
+        msg: &Message,
+        service_name: &Name,
+        nonce: [u8; NONCE_LEN],
+    ) -> Option<Message> {
+        let time = self.clock.now().to_be_bytes();
+        let tag = self
+            .mac(msg, service_name, &nonce, &time)?
+            .finalize()
+            .into_bytes();
+        let mut sealed = msg.clone();
+        sealed.add_additional(Record::from_rdata(
+            service_name.clone(),
+            0,
+            RData::TXT(TXT::new(vec![
+                format!("nonce={}", BASE64_NOPAD.encode(&nonce)),
+                format!("time={}", BASE64_NOPAD.encode(&time)),
+                format!("mac={}", BASE64_NOPAD.encode(&tag)),
+            ])),
+        ));
+        Some(sealed)
+    }
+
+    /// Check whether `msg` was recently sent by a holder of this secret.
+    pub fn verify(&self, msg: &Message, service_name: &Name) -> bool {
+        let Some(authenticator) = msg
+            .additionals
+            .iter()
+            .filter(|record| is_authenticator(record, service_name))
+            .find_map(parse_authenticator)
+        else {
+            return false;
+        };
+        let Ok(time) = <[u8; 8]>::try_from(&authenticator.time[..]) else {
+            return false;
+        };
+        if u64::from_be_bytes(time).abs_diff(self.clock.now()) > MAX_SKEW.as_secs() {
+            tracing::debug!("ignoring packet sent at another time");
+            return false;
+        }
+        self.mac(msg, service_name, &authenticator.nonce, &time)
+            .is_some_and(|mac| mac.verify_slice(&authenticator.tag).is_ok())
+    }
+
+    fn mac(
+        &self,
+        msg: &Message,
+        service_name: &Name,
+        nonce: &[u8],
+        time: &[u8; 8],
+    ) -> Option<HmacSha256> {
+        fn push(mac: &mut HmacSha256, bytes: &[u8]) {
+            mac.update(&(bytes.len() as u32).to_be_bytes());
+            mac.update(bytes);
+        }
+
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(CONTEXT);
+        push(&mut mac, nonce);
+        mac.update(time);
+        mac.update(&(msg.queries.len() as u32).to_be_bytes());
+        for query in &msg.queries {
+            push(&mut mac, &query.to_bytes().ok()?);
+        }
+        mac.update(&(msg.answers.len() as u32).to_be_bytes());
+        for record in &msg.answers {
+            push(&mut mac, &record.to_bytes().ok()?);
+        }
+        mac.update(&(msg.authorities.len() as u32).to_be_bytes());
+        for record in &msg.authorities {
+            push(&mut mac, &record.to_bytes().ok()?);
+        }
+        let additionals = msg
+            .additionals
+            .iter()
+            .filter(|record| !is_authenticator(record, service_name));
+        mac.update(&(additionals.clone().count() as u32).to_be_bytes());
+        for record in additionals {
+            push(&mut mac, &record.to_bytes().ok()?);
+        }
+        Some(mac)
+    }
+}
+
+/// Size of the authenticator record added by [SwarmSecret::seal], without name compression.
+pub(crate) fn overhead(service_name: &Name) -> usize {
+    // type, class, TTL and data length
+    const FIXED: usize = 10;
+    // each string has a length byte, unpadded base64 encodes n bytes in ⌈4n/3⌉ characters
+    const NONCE: usize = 1 + "nonce=".len() + (4 * NONCE_LEN).div_ceil(3);
+    const TIME: usize = 1 + "time=".len() + (4 * 8usize).div_ceil(3);
+    const MAC: usize = 1 + "mac=".len() + (4 * 32usize).div_ceil(3);
+    service_name.len() + 1 + FIXED + NONCE + TIME + MAC
+}
+
+fn is_authenticator(record: &Record, service_name: &Name) -> bool {
+    record.record_type() == RecordType::TXT && record.name == *service_name
+}
+
+/// The decoded strings of an authenticator record.
+struct Authenticator {
+    nonce: Vec<u8>,
+    time: Vec<u8>,
+    tag: Vec<u8>,
+}
+
+fn parse_authenticator(record: &Record) -> Option<Authenticator> {
+    let RData::TXT(txt) = &record.data else {
+        return None;
+    };
+    let mut nonce = None;
+    let mut time = None;
+    let mut tag = None;
+    for s in txt.txt_data.iter() {
+        let (key, value) = std::str::from_utf8(s).ok()?.split_once('=')?;
+        let value = BASE64_NOPAD.decode(value.as_bytes()).ok()?;
+        match key {
+            "nonce" => nonce = Some(value),
+            "time" => time = Some(value),
+            "mac" => tag = Some(value),
+            _ => {}
+        }
+    }
+    Some(Authenticator {
+        nonce: nonce?,
+        time: time?,
+        tag: tag?,
+    })
+}
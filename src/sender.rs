@@ -1,6 +1,9 @@
 use crate::{
-    guardian,
-    socket::{Mode, Sockets},
+    conflict::{Conflicts, ProbeOutcome},
+    names, packets,
+    socket::Mode,
+    stats::{Counter, Counters},
+    transport::Transport,
     updater, Discoverer, Peer,
 };
 use acto::{AcTokioRuntime, ActoCell, ActoInput, ActoRef};
@@ -11,140 +14,454 @@ use hickory_proto::{
         DNSClass, Name, RData, Record, RecordType,
     },
 };
-use rand::{rng, RngExt};
-use std::{collections::BTreeMap, net::IpAddr, str::FromStr, time::Duration};
+use rand::{rngs::StdRng, RngExt, SeedableRng};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::Instant};
 
 const RESPONSE_DELAY: Duration = Duration::from_millis(100);
 
+/// TTL of SRV and address records, see RFC 6762 section 10.
+const HOST_TTL: Duration = Duration::from_secs(120);
+/// TTL of other records, see RFC 6762 section 10.
+const OTHER_TTL: u32 = 4500;
+/// TTL of the service type in enumeration responses.
+pub const ENUMERATION_TTL: u32 = OTHER_TTL;
+/// Name queried for to enumerate service types, see RFC 6763 section 9.
+pub const SERVICES: &str = "_services._dns-sd._udp.local.";
+/// Space in a query available for known answers, bounding the query traffic in large swarms.
+const KNOWN_ANSWER_BUDGET: usize = 1200;
+/// Minimum delay before responding to a query whose known answers continue in further packets.
+///
+/// RFC 6762 section 7.2 suggests 400–500ms, but continuation packets are sent right after the
+/// first one by this crate and common implementations; waiting that long would make the
+/// responder miss the queries of other peers in the meantime.
+const TRUNCATED_DELAY: Duration = Duration::from_millis(20);
+
+/// Instance labels listed in a query as already known, with the remaining TTL in seconds.
+pub type KnownAnswers = BTreeMap<String, u32>;
+
+/// The known answers of a query, see RFC 6762 section 7.1.
+pub struct Known {
+    /// the querier, `None` for our own queries
+    pub source: Option<IpAddr>,
+    pub answers: KnownAnswers,
+    /// whether more known answers follow in further packets (TC bit)
+    pub truncated: bool,
+    /// the subtype queried for, `None` for the whole service
+    pub subtype: Option<String>,
+}
+
+/// Changes to the announcement of the local peer, forwarded by the guardian.
+pub enum Update {
+    RemoveAll,
+    RemovePort(u16),
+    RemoveAddr(IpAddr),
+    AddAddr(u16, Vec<IpAddr>),
+    RemoveAddrs(u16, Vec<IpAddr>),
+    SetTxt(String, Option<Vec<u8>>),
+    RemoveTxt(String),
+    AddSubtype(String),
+    RemoveSubtype(String),
+}
+
 pub enum MdnsMsg {
-    QueryV4,
-    QueryV6,
+    QueryV4(Known),
+    QueryV6(Known),
+    /// Known answers continuing a truncated query.
+    MoreKnown(Known),
     Response(BTreeMap<String, Peer>),
+    /// Subtypes withdrawn by goodbyes, keyed by instance label.
+    Withdrawn(BTreeMap<String, BTreeSet<String>>),
+    /// Records claimed in a probe by another instance.
+    Probe(BTreeMap<String, Peer>),
+    ProbeTimeout(usize),
+    /// Query for the records of an instance by its label.
+    Resolve(String, Mode),
+    /// Service type enumeration query not yet listing our service type.
+    Enumerate(Mode),
     Timeout(usize),
     SizeUpdate(usize),
-    Update(guardian::Input),
+    /// Report the encoded size of the current announcement.
+    GetSize(oneshot::Sender<usize>),
+    Update(Update),
+    SetCadence(Duration),
+    SetResponseRate(f32),
+    /// Send a goodbye announcement and stop.
+    Stop,
+}
+
+/// State shared between waiting for a query and waiting to respond to it.
+struct State {
+    transport: Arc<dyn Transport>,
+    updater: ActoRef<updater::Input>,
+    discoverer: Discoverer,
+    service_name: Name,
+    stats: Arc<Counters>,
+    rng: StdRng,
+    tau: Duration,
+    phi: f32,
+    /// number of responses per cycle after which we do not respond, ceil(τ•φ)
+    cutoff: u32,
+    swarm_size: usize,
+    ttl: u32,
+    response: Option<Message>,
+    conflicts: Conflicts,
+    /// responses received in the current cycle, which continues after leaving response mode
+    response_count: u32,
+}
+
+/// How the current phase of the sender continues after a message was handled.
+enum Flow {
+    Continue,
+    /// The cadence was changed.
+    Cadence,
+    /// The cutoff of responses in the current cycle was reached.
+    Cutoff,
+    /// The goodbye announcement was sent, the sender stops.
+    Stop,
+    /// The message is handled depending on the phase.
+    Phase(MdnsMsg),
+}
+
+impl State {
+    /// Handle the messages that are treated alike in both phases.
+    async fn handle(&mut self, msg: MdnsMsg) -> Flow {
+        match msg {
+            msg @ (MdnsMsg::QueryV4(_) | MdnsMsg::QueryV6(_)) => {
+                self.stats.add(Counter::QueriesReceived, 1);
+                return Flow::Phase(msg);
+            }
+            msg @ (MdnsMsg::MoreKnown(_) | MdnsMsg::Timeout(_)) => return Flow::Phase(msg),
+            MdnsMsg::Response(mut resp) => {
+                self.stats
+                    .responses(&mut self.response_count, resp.len() as u32, self.cutoff);
+                if let Some(foreign) = self.conflicts.check_response(&mut resp, &self.discoverer) {
+                    self.resolve(foreign);
+                }
+                self.updater.send(updater::Input::Peers(resp));
+                if self.response_count >= self.cutoff {
+                    return Flow::Cutoff;
+                }
+            }
+            MdnsMsg::Withdrawn(withdrawn) => {
+                self.updater.send(updater::Input::Withdrawn(withdrawn));
+            }
+            MdnsMsg::Probe(claims) => match self.conflicts.check_probe(&claims, &self.discoverer) {
+                ProbeOutcome::Ignore => {}
+                ProbeOutcome::Defend => {
+                    if let Some(response) = &self.response {
+                        let packets = outgoing(
+                            response,
+                            &self.discoverer,
+                            &self.service_name,
+                            &mut self.rng,
+                        );
+                        send(&*self.transport, packets, Mode::Any).await;
+                    }
+                }
+                ProbeOutcome::Conflict(foreign) => self.resolve(foreign),
+            },
+            MdnsMsg::Resolve(peer_id, mode) => {
+                let response =
+                    instance_response(&peer_id, &self.response, &self.conflicts, &self.discoverer);
+                if let Some(response) = response {
+                    let packets = outgoing(
+                        response,
+                        &self.discoverer,
+                        &self.service_name,
+                        &mut self.rng,
+                    );
+                    send(&*self.transport, packets, mode).await;
+                }
+            }
+            MdnsMsg::Enumerate(mode) => {
+                if self.response.is_some() && !self.conflicts.is_probing() {
+                    enumerate(
+                        &self.transport,
+                        &self.discoverer,
+                        &self.service_name,
+                        mode,
+                        &mut self.rng,
+                    );
+                }
+            }
+            MdnsMsg::ProbeTimeout(generation) => {
+                let probe = self.conflicts.next_probe(
+                    generation,
+                    &self.response,
+                    &self.discoverer,
+                    &self.service_name,
+                );
+                if let Some(probe) = probe {
+                    let packets =
+                        outgoing(&probe, &self.discoverer, &self.service_name, &mut self.rng);
+                    send(&*self.transport, packets, Mode::Any).await;
+                }
+            }
+            MdnsMsg::SizeUpdate(size) => {
+                self.swarm_size = size;
+                self.stats.set_swarm_size(size);
+            }
+            MdnsMsg::GetSize(reply) => {
+                let _ = reply.send(announcement_size(
+                    &self.response,
+                    &self.discoverer,
+                    &self.service_name,
+                ));
+            }
+            MdnsMsg::Update(msg) => {
+                let withdrawn = withdrawn(
+                    &msg,
+                    &self.response,
+                    &self.discoverer,
+                    &self.service_name,
+                    &mut self.rng,
+                );
+                send_goodbye(&*self.transport, withdrawn).await;
+                self.conflicts.claim(&self.discoverer, self.ttl);
+                let had_response = self.response.is_some();
+                self.response =
+                    update_response(&mut self.discoverer, &self.service_name, self.ttl, msg);
+                if !had_response && self.response.is_some() {
+                    self.conflicts.start_probing(&mut self.rng);
+                }
+            }
+            MdnsMsg::SetCadence(tau) => {
+                self.tau = tau;
+                self.cutoff = cutoff(self.tau, self.phi);
+                return Flow::Cadence;
+            }
+            MdnsMsg::SetResponseRate(phi) => {
+                self.phi = phi;
+                self.cutoff = cutoff(self.tau, self.phi);
+            }
+            MdnsMsg::Stop => {
+                let goodbye = goodbye(&self.discoverer, &self.service_name, &mut self.rng);
+                send_goodbye(&*self.transport, goodbye).await;
+                return Flow::Stop;
+            }
+        }
+        Flow::Continue
+    }
+
+    /// Report a conflict with another instance and rebuild the response if renamed.
+    fn resolve(&mut self, foreign: Peer) {
+        if self.conflicts.resolve(
+            foreign,
+            &mut self.discoverer,
+            &self.service_name,
+            &mut self.rng,
+        ) {
+            self.response = make_response(&self.discoverer, &self.service_name, self.ttl);
+        }
+    }
 }
 
 pub async fn sender(
     mut ctx: ActoCell<MdnsMsg, AcTokioRuntime>,
-    sockets: Sockets,
+    transport: Arc<dyn Transport>,
     updater: ActoRef<updater::Input>,
-    mut discoverer: Discoverer,
+    discoverer: Discoverer,
     service_name: Name,
+    stats: Arc<Counters>,
 ) {
+    let rng = match discoverer.rng_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => rand::make_rng(),
+    };
     let tau = discoverer.tau;
     let phi = discoverer.phi;
-    let cutoff = (tau.as_secs_f32() * phi).ceil() as u32;
-
-    let query = make_query(&service_name);
-    let mut response = make_response(&discoverer, &service_name);
-
-    let mut timeout_count = 0;
+    let swarm_size = 1;
+    let ttl = record_ttl(tau, phi, swarm_size);
+    let response = make_response(&discoverer, &service_name, ttl);
+    let browse = match &discoverer.browse_subtype {
+        Some(subtype) => names::subtype_name(subtype, &service_name).expect("checked in builder"),
+        None => service_name.clone(),
+    };
+    let conflicts = Conflicts::new(ctx.me(), updater.clone(), &discoverer);
+    let mut state = State {
+        transport,
+        updater,
+        discoverer,
+        service_name,
+        stats,
+        rng,
+        tau,
+        phi,
+        cutoff: cutoff(tau, phi),
+        swarm_size,
+        ttl,
+        response,
+        conflicts,
+        response_count: 0,
+    };
+    if state.response.is_some() {
+        state.conflicts.start_probing(&mut state.rng);
+    }
 
-    updater.send(updater::Input::SizeSubscription(
+    state.updater.send(updater::Input::SizeSubscription(
         ctx.me().contramap(MdnsMsg::SizeUpdate),
     ));
 
-    let mut swarm_size = 1;
+    let mut timeout_count = 0;
     let mut extra_delay = Duration::ZERO;
     let mut has_responded = false;
 
     loop {
-        let me = ctx.me();
-        let timeout = tokio::spawn(async move {
+        let (mode, mut known) = 'query: loop {
+            let me = ctx.me();
             // grow the interval from which the randomized part is draw
             // with the swarm size to keep the number of duplicates low
-            let interval = tau * swarm_size as u32 / 10;
-            let millionth = rng().random_range(0..1_000_000);
-            let delay = tau + interval / 1_000_000 * millionth;
-            tracing::debug!(?delay, "waiting for query");
-            tokio::time::sleep(delay).await;
-            me.send(MdnsMsg::Timeout(timeout_count));
-        });
+            let interval = state.tau * state.swarm_size as u32 / 10;
+            let millionth = state.rng.random_range(0..1_000_000);
+            let delay = state.tau + interval / 1_000_000 * millionth;
+            let timeout = tokio::spawn(async move {
+                tracing::debug!(?delay, "waiting for query");
+                tokio::time::sleep(delay).await;
+                me.send(MdnsMsg::Timeout(timeout_count));
+            });
 
-        let mode = loop {
-            if let ActoInput::Message(msg) = ctx.recv().await {
-                match msg {
-                    MdnsMsg::QueryV4 => {
+            loop {
+                let ActoInput::Message(msg) = ctx.recv().await else {
+                    return;
+                };
+                match state.handle(msg).await {
+                    Flow::Continue | Flow::Cutoff => {}
+                    Flow::Cadence => {
+                        // restart the wait so that the new cadence takes effect immediately
                         timeout.abort();
-                        break Mode::V4;
+                        timeout_count += 1;
+                        continue 'query;
                     }
-                    MdnsMsg::QueryV6 => {
+                    Flow::Stop => {
                         timeout.abort();
-                        break Mode::V6;
+                        return;
                     }
-                    MdnsMsg::Response(resp) => {
-                        updater.send(updater::Input::Peers(resp));
-                    }
-                    MdnsMsg::Timeout(count) if count == timeout_count => {
-                        sockets.send_msg(&query, Mode::Any).await;
-                        break Mode::Any;
+                    Flow::Phase(MdnsMsg::QueryV4(known)) if is_asked(&known, &state.discoverer) => {
+                        timeout.abort();
+                        break 'query (Mode::V4, known);
                     }
-                    MdnsMsg::Timeout(_) => {}
-                    MdnsMsg::SizeUpdate(size) => {
-                        swarm_size = size;
+                    Flow::Phase(MdnsMsg::QueryV6(known)) if is_asked(&known, &state.discoverer) => {
+                        timeout.abort();
+                        break 'query (Mode::V6, known);
                     }
-                    MdnsMsg::Update(msg) => {
-                        response = update_response(&mut discoverer, &service_name, msg);
+                    Flow::Phase(MdnsMsg::Timeout(count)) if count == timeout_count => {
+                        // peers heard from in the last S/φ need not respond this time
+                        let fresh =
+                            updater::grace_period(state.tau, state.phi, state.swarm_size) / 3;
+                        let answers = known_answers(
+                            &state.updater,
+                            fresh,
+                            state.discoverer.browse_subtype.as_deref(),
+                        )
+                        .await;
+                        let query = make_query(&browse, &state.service_name, &answers);
+                        let packets = outgoing(
+                            &query,
+                            &state.discoverer,
+                            &state.service_name,
+                            &mut state.rng,
+                        );
+                        send(&*state.transport, packets, Mode::Any).await;
+                        state.stats.add(Counter::QueriesSent, 1);
+                        let known = Known {
+                            source: None,
+                            answers,
+                            truncated: false,
+                            subtype: state.discoverer.browse_subtype.clone(),
+                        };
+                        break 'query (Mode::Any, known);
                     }
+                    Flow::Phase(_) => {}
                 }
-            } else {
-                return;
             }
         };
 
         timeout_count += 1;
 
+        // announce records for as long as the other peers would keep them without hearing from us
+        let ttl = record_ttl(state.tau, state.phi, state.swarm_size);
+        if ttl != state.ttl {
+            state.ttl = ttl;
+            state.response = make_response(&state.discoverer, &state.service_name, ttl);
+        }
+
         let me = ctx.me();
         // for fairness: if we have sent and the swarm is large, delay some more
         if has_responded {
-            extra_delay = RESPONSE_DELAY * (swarm_size as u32 / cutoff).min(10);
+            extra_delay = RESPONSE_DELAY * (state.swarm_size as u32 / state.cutoff).min(10);
         } else {
             extra_delay = extra_delay.checked_sub(RESPONSE_DELAY).unwrap_or_default();
         }
+        state.stats.set_extra_delay(extra_delay);
+        // grow the interval from which the randomized part is draw
+        // with the swarm size to keep the number of duplicates low
+        // goal is "cutoff within 100ms"
+        let interval = RESPONSE_DELAY * state.swarm_size as u32 / state.cutoff;
+        let millionth = state.rng.random_range(0..1_000_000);
+        let mut delay = interval / 1_000_000 * millionth;
+        delay += extra_delay;
+        if known.truncated {
+            // wait for the rest of the known answers
+            delay = delay.max(TRUNCATED_DELAY);
+        }
         let timeout = tokio::spawn(async move {
-            // grow the interval from which the randomized part is draw
-            // with the swarm size to keep the number of duplicates low
-            // goal is "cutoff within 100ms"
-            let interval = RESPONSE_DELAY * swarm_size as u32 / cutoff;
-            let millionth = rng().random_range(0..1_000_000);
-            let mut delay = interval / 1_000_000 * millionth;
-            delay += extra_delay;
             tracing::debug!(?delay, "waiting to respond");
             tokio::time::sleep(delay).await;
             me.send(MdnsMsg::Timeout(timeout_count));
         });
 
-        let mut response_count = 0;
+        state.response_count = 0;
         has_responded = false;
         loop {
-            if let ActoInput::Message(msg) = ctx.recv().await {
-                match msg {
-                    MdnsMsg::Response(resp) => {
-                        response_count += resp.len() as u32;
-                        updater.send(updater::Input::Peers(resp));
-                        if response_count >= cutoff {
-                            timeout.abort();
-                            break;
-                        }
-                    }
-                    MdnsMsg::Timeout(count) if count == timeout_count => {
-                        if let Some(response) = &response {
-                            sockets.send_msg(response, mode).await;
-                            has_responded = true;
-                        }
-                        break;
-                    }
-                    MdnsMsg::SizeUpdate(size) => {
-                        swarm_size = size;
+            let ActoInput::Message(msg) = ctx.recv().await else {
+                return;
+            };
+            match state.handle(msg).await {
+                Flow::Continue | Flow::Cadence => {}
+                Flow::Cutoff => {
+                    state.stats.add(Counter::ResponsesSuppressed, 1);
+                    timeout.abort();
+                    break;
+                }
+                Flow::Stop => {
+                    timeout.abort();
+                    return;
+                }
+                Flow::Phase(MdnsMsg::MoreKnown(more)) => {
+                    if more.source.is_some() && more.source == known.source {
+                        known.answers.extend(more.answers);
                     }
-                    MdnsMsg::Update(msg) => {
-                        response = update_response(&mut discoverer, &service_name, msg);
+                }
+                Flow::Phase(MdnsMsg::Timeout(count)) if count == timeout_count => {
+                    if !is_asked(&known, &state.discoverer) {
+                        tracing::debug!("not of the subtype queried for, not responding");
+                    } else if is_known(&known.answers, &state.discoverer, state.ttl) {
+                        tracing::debug!("listed as known answer, not responding");
+                        state.stats.add(Counter::ResponsesKnown, 1);
+                    } else if state.conflicts.is_probing() {
+                        tracing::debug!("peer ID not confirmed yet, not responding");
+                    } else if let Some(response) = &state.response {
+                        let packets = outgoing(
+                            response,
+                            &state.discoverer,
+                            &state.service_name,
+                            &mut state.rng,
+                        );
+                        send(&*state.transport, packets, mode).await;
+                        state.stats.add(Counter::ResponsesSent, 1);
+                        has_responded = true;
                     }
-                    MdnsMsg::QueryV4 => {}
-                    MdnsMsg::QueryV6 => {}
-                    MdnsMsg::Timeout(_) => {}
+                    break;
                 }
+                Flow::Phase(_) => {}
             }
         }
 
@@ -152,25 +469,267 @@ pub async fn sender(
     }
 }
 
-fn make_query(service_name: &Name) -> Message {
+/// The response to send for a query resolving the given instance, if it is ours.
+///
+/// Records with a unique owner name are answered immediately, see RFC 6762 section 6.
+fn instance_response<'a>(
+    peer_id: &str,
+    response: &'a Option<Message>,
+    conflicts: &Conflicts,
+    discoverer: &Discoverer,
+) -> Option<&'a Message> {
+    if peer_id != names::label_text(&discoverer.peer_id) || conflicts.is_probing() {
+        return None;
+    }
+    response.as_ref()
+}
+
+/// Answer a service type enumeration query after a random delay of 20–120ms.
+///
+/// The delay is mandated for shared records by RFC 6762 section 6, since every peer answers.
+fn enumerate(
+    transport: &Arc<dyn Transport>,
+    discoverer: &Discoverer,
+    service_name: &Name,
+    mode: Mode,
+    rng: &mut StdRng,
+) {
+    let mut msg = Message::new(0, MessageType::Response, OpCode::Query);
+    msg.metadata.authoritative = true;
+    msg.add_answer(Record::from_rdata(
+        Name::from_str(SERVICES).unwrap(),
+        ENUMERATION_TTL,
+        RData::PTR(rdata::PTR(service_name.clone())),
+    ));
+    let msg = seal(&msg, discoverer, service_name, rng).into_owned();
+    let delay = Duration::from_millis(rng.random_range(20..=120));
+    let transport = transport.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        transport.send_msg(&msg, mode).await;
+    });
+}
+
+/// Split a message into packets of the configured size, each authenticated if needed.
+fn outgoing(
+    msg: &Message,
+    discoverer: &Discoverer,
+    service_name: &Name,
+    rng: &mut StdRng,
+) -> Vec<Message> {
+    packets::split(msg, discoverer.payload_size(service_name))
+        .into_iter()
+        .map(|packet| seal(&packet, discoverer, service_name, rng).into_owned())
+        .collect()
+}
+
+/// The number of bytes sent for an announcement, summed over all its packets.
+///
+/// Computed without authenticating the packets to not draw nonces from the seeded generator.
+fn announcement_size(
+    response: &Option<Message>,
+    discoverer: &Discoverer,
+    service_name: &Name,
+) -> usize {
+    let Some(response) = response else {
+        return 0;
+    };
+    let payload_size = discoverer.payload_size(service_name);
+    let overhead = discoverer.max_packet_size - payload_size;
+    packets::split(response, payload_size)
+        .iter()
+        .map(|packet| packets::encoded_len(packet) + overhead)
+        .sum()
+}
+
+async fn send(transport: &dyn Transport, packets: Vec<Message>, mode: Mode) {
+    for packet in packets {
+        transport.send_msg(&packet, mode).await;
+    }
+}
+
+/// Authenticate an outgoing message if a swarm secret is configured.
+#[cfg_attr(not(feature = "swarm-secret"), allow(unused_variables))]
+fn seal<'a>(
+    msg: &'a Message,
+    discoverer: &Discoverer,
+    service_name: &Name,
+    rng: &mut StdRng,
+) -> Cow<'a, Message> {
+    #[cfg(feature = "swarm-secret")]
+    if let Some(secret) = &discoverer.swarm_secret {
+        let nonce = rng.random::<[u8; crate::secret::NONCE_LEN]>();
+        match secret.seal(msg, service_name, nonce) {
+            Some(sealed) => return Cow::Owned(sealed),
+            None => tracing::warn!("cannot authenticate message, sending it without"),
+        }
+    }
+    Cow::Borrowed(msg)
+}
+
+/// The number of responses per cycle after which we do not respond, ceil(τ•φ) but at least one.
+fn cutoff(tau: Duration, phi: f32) -> u32 {
+    ((tau.as_secs_f32() * phi).ceil() as u32).max(1)
+}
+
+/// The TTL of announced host records.
+///
+/// RFC 6762 recommends 120 seconds, but in large swarms each peer responds less often than that.
+fn record_ttl(tau: Duration, phi: f32, swarm_size: usize) -> u32 {
+    let ttl = updater::grace_period(tau, phi, swarm_size).max(HOST_TTL);
+    ttl.as_secs_f32().ceil() as u32
+}
+
+/// Build the goodbye announcement for the current response, i.e. all its records with TTL 0.
+///
+/// The response is built anew rather than copied, so that its signature covers the goodbye.
+fn goodbye(discoverer: &Discoverer, service_name: &Name, rng: &mut StdRng) -> Vec<Message> {
+    let Some(goodbye) = make_response(discoverer, service_name, 0) else {
+        return Vec::new();
+    };
+    outgoing(&goodbye, discoverer, service_name, rng)
+}
+
+/// Build the goodbye announcement for the records withdrawn by an update, if any.
+fn withdrawn(
+    msg: &Update,
+    response: &Option<Message>,
+    discoverer: &Discoverer,
+    service_name: &Name,
+    rng: &mut StdRng,
+) -> Vec<Message> {
+    match msg {
+        Update::RemoveAll => goodbye(discoverer, service_name, rng),
+        Update::RemoveSubtype(subtype) => {
+            let Some(response) = response else {
+                return Vec::new();
+            };
+            let mut goodbye = Message::new(0, MessageType::Response, OpCode::Query);
+            goodbye.metadata.authoritative = true;
+            for record in &response.answers {
+                if names::subtype_of(&record.name, service_name) == Some(subtype.as_str()) {
+                    let mut record = record.clone();
+                    record.ttl = 0;
+                    goodbye.add_answer(record);
+                }
+            }
+            if goodbye.answers.is_empty() {
+                return Vec::new();
+            }
+            outgoing(&goodbye, discoverer, service_name, rng)
+        }
+        _ => Vec::new(),
+    }
+}
+
+async fn send_goodbye(transport: &dyn Transport, goodbye: Vec<Message>) {
+    if !goodbye.is_empty() {
+        tracing::debug!("sending goodbye announcement");
+        send(transport, goodbye, Mode::Any).await;
+    }
+}
+
+/// Collect the peers heard from within `fresh` for the known-answer list of a query.
+///
+/// Only peers announcing the browsed subtype are listed, if any. The most recently heard peers
+/// come first and the list is cut off at [KNOWN_ANSWER_BUDGET].
+async fn known_answers(
+    updater: &ActoRef<updater::Input>,
+    fresh: Duration,
+    subtype: Option<&str>,
+) -> KnownAnswers {
+    let (tx, rx) = oneshot::channel();
+    updater.send(updater::Input::GetPeers(tx));
+    let Ok(peers) = rx.await else {
+        return KnownAnswers::new();
+    };
+    let now = Instant::now();
+    let subtype = subtype.map(str::to_ascii_lowercase);
+    let mut fresh_peers = peers
+        .into_iter()
+        .filter(|(_, peer)| subtype.as_ref().is_none_or(|s| peer.subtypes.contains(s)))
+        .filter_map(|(peer_id, peer)| {
+            let age = now.saturating_duration_since(peer.last_seen);
+            let remaining = peer.ttl.saturating_sub(age).as_secs().min(u32::MAX.into()) as u32;
+            // peers of older versions announce no TTL that could be listed
+            (age < fresh && remaining > 0).then_some((age, peer_id, remaining))
+        })
+        .collect::<Vec<_>>();
+    fresh_peers.sort_unstable();
+    let mut budget = KNOWN_ANSWER_BUDGET;
+    fresh_peers
+        .into_iter()
+        .map_while(|(_, peer_id, remaining)| {
+            let label = names::label_text(&peer_id).into_owned();
+            // compressed owner name, fixed fields and compressed target name
+            budget = budget.checked_sub(label.len() + 15)?;
+            Some((label, remaining))
+        })
+        .collect()
+}
+
+/// Whether the querier already knows our records well enough, see RFC 6762 section 7.1.
+fn is_known(known: &KnownAnswers, discoverer: &Discoverer, ttl: u32) -> bool {
+    known
+        .get(&*names::label_text(&discoverer.peer_id))
+        .is_some_and(|remaining| *remaining >= ttl / 2)
+}
+
+/// Whether a query is for the whole service or for a subtype announced by this peer.
+fn is_asked(known: &Known, discoverer: &Discoverer) -> bool {
+    match &known.subtype {
+        // DNS names are compared case-insensitively, see RFC 6762 section 16
+        Some(subtype) => discoverer
+            .subtypes
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(subtype)),
+        None => true,
+    }
+}
+
+/// A query for `browse`, i.e. the service name or one of its subtypes.
+fn make_query(browse: &Name, service_name: &Name, known: &KnownAnswers) -> Message {
     let mut msg = Message::new(0, MessageType::Query, OpCode::Query);
     let mut query = Query::new();
     query.set_query_class(DNSClass::IN);
     query.set_query_type(RecordType::PTR);
-    query.set_name(service_name.clone());
+    query.set_name(browse.clone());
     msg.add_query(query);
+    for (label, remaining) in known {
+        let Ok(instance) = names::instance_name(label, service_name) else {
+            continue;
+        };
+        msg.add_answer(Record::from_rdata(
+            browse.clone(),
+            *remaining,
+            RData::PTR(rdata::PTR(instance)),
+        ));
+    }
     msg
 }
 
-fn make_response(discoverer: &Discoverer, service_name: &Name) -> Option<Message> {
+fn make_response(discoverer: &Discoverer, service_name: &Name, ttl: u32) -> Option<Message> {
     if let Some(peer) = discoverer.peers.get(&discoverer.peer_id) {
         let mut msg = Message::new(0, MessageType::Response, OpCode::Query);
         msg.metadata.authoritative = true;
 
-        let my_srv_name = Name::from_str(&discoverer.peer_id)
-            .expect("PeerId was checked in spawn()")
-            .append_domain(service_name)
-            .expect("was checked in spawn()");
+        let my_srv_name = names::instance_name(&discoverer.peer_id, service_name)
+            .expect("PeerId was checked in spawn()");
+
+        // the answer to the browsing query, see RFC 6763 section 12.1
+        msg.add_answer(Record::from_rdata(
+            service_name.clone(),
+            ttl,
+            RData::PTR(rdata::PTR(my_srv_name.clone())),
+        ));
+        // subtypes answer browsing queries for them, see RFC 6763 section 7.1
+        for subtype in &discoverer.subtypes {
+            msg.add_answer(Record::from_rdata(
+                names::subtype_name(subtype, service_name).expect("checked in builder"),
+                ttl,
+                RData::PTR(rdata::PTR(my_srv_name.clone())),
+            ));
+        }
 
         let mut srv_map = BTreeMap::new();
         for (ip, port) in &peer.addrs {
@@ -178,11 +737,13 @@ fn make_response(discoverer: &Discoverer, service_name: &Name) -> Option<Message
         }
 
         for (port, addrs) in srv_map {
-            let target = Name::from_str(&format!("{}-{}.local.", discoverer.peer_id, port))
-                .expect("PeerId was checked in spawn()");
+            let target =
+                names::host_name(&discoverer.peer_id, port).expect("PeerId was checked in spawn()");
+            // RFC 6763 section 12.1 suggests additional records, but versions before 0.7 read
+            // SRV and TXT records only from the answers and addresses only from the additionals
             msg.add_answer(Record::from_rdata(
                 my_srv_name.clone(),
-                0,
+                ttl,
                 RData::SRV(rdata::SRV::new(0, 0, port, target.clone())),
             ));
             for addr in addrs {
@@ -190,21 +751,35 @@ fn make_response(discoverer: &Discoverer, service_name: &Name) -> Option<Message
                     IpAddr::V4(addr) => {
                         msg.add_additional(Record::from_rdata(
                             target.clone(),
-                            0,
+                            ttl,
                             RData::A(rdata::A::from(addr)),
                         ));
                     }
                     IpAddr::V6(addr) => {
                         msg.add_additional(Record::from_rdata(
                             target.clone(),
-                            0,
+                            ttl,
                             RData::AAAA(rdata::AAAA::from(addr)),
                         ));
                     }
                 }
             }
         }
-        if !peer.txt.is_empty() {
+        #[cfg(feature = "signing")]
+        let signature = discoverer.signing_key.as_ref().map(|key| {
+            let goodbye = ttl == 0;
+            let signature =
+                crate::signing::sign(key, service_name, &discoverer.peer_id, peer, goodbye);
+            format!("{}={}", names::SIGNATURE_KEY, signature).into_bytes()
+        });
+        #[cfg(not(feature = "signing"))]
+        let signature = None::<Vec<u8>>;
+        // a peer ID too long for the instance label is announced in full
+        let full_id = match names::is_abbreviated(&discoverer.peer_id) {
+            true => names::peer_id_parts(&discoverer.peer_id).collect(),
+            false => Vec::new(),
+        };
+        if !peer.txt.is_empty() || signature.is_some() || !full_id.is_empty() {
             let parts = peer
                 .txt
                 .iter()
@@ -212,15 +787,20 @@ fn make_response(discoverer: &Discoverer, service_name: &Name) -> Option<Message
                     if k.is_empty() {
                         None
                     } else {
-                        Some(match v {
-                            None => k.to_string(),
-                            Some(v) => format!("{k}={v}"),
-                        })
+                        let mut part = k.as_bytes().to_vec();
+                        if let Some(v) = v {
+                            part.push(b'=');
+                            part.extend_from_slice(v);
+                        }
+                        Some(part)
                     }
                 })
-                .collect();
-            let rdata = TXT::new(parts);
-            let record = Record::from_rdata(my_srv_name, 0, RData::TXT(rdata));
+                .chain(full_id)
+                .chain(signature)
+                .collect::<Vec<_>>();
+            let rdata = TXT::from_bytes(parts.iter().map(Vec::as_slice).collect());
+            let txt_ttl = if ttl == 0 { 0 } else { ttl.max(OTHER_TTL) };
+            let record = Record::from_rdata(my_srv_name, txt_ttl, RData::TXT(rdata));
             msg.add_answer(record);
         }
         Some(msg)
@@ -233,26 +813,35 @@ fn make_response(discoverer: &Discoverer, service_name: &Name) -> Option<Message
 fn update_response(
     discoverer: &mut Discoverer,
     service_name: &Name,
-    msg: guardian::Input,
+    ttl: u32,
+    msg: Update,
 ) -> Option<Message> {
     match msg {
-        guardian::Input::RemoveAll => {
+        Update::RemoveAll => {
             discoverer.peers.remove(&discoverer.peer_id);
-            make_response(discoverer, service_name)
+            make_response(discoverer, service_name, ttl)
         }
-        guardian::Input::RemovePort(port) => {
+        Update::RemovePort(port) => {
             if let Some(peers) = discoverer.peers.get_mut(&discoverer.peer_id) {
                 peers.addrs.retain(|(_, p)| *p != port);
             }
-            make_response(discoverer, service_name)
+            make_response(discoverer, service_name, ttl)
         }
-        guardian::Input::RemoveAddr(addr) => {
+        Update::RemoveAddr(addr) => {
             if let Some(peers) = discoverer.peers.get_mut(&discoverer.peer_id) {
                 peers.addrs.retain(|(a, _)| *a != addr);
             }
-            make_response(discoverer, service_name)
+            make_response(discoverer, service_name, ttl)
+        }
+        Update::RemoveAddrs(port, addrs) => {
+            if let Some(peers) = discoverer.peers.get_mut(&discoverer.peer_id) {
+                peers
+                    .addrs
+                    .retain(|(a, p)| *p != port || !addrs.contains(a));
+            }
+            make_response(discoverer, service_name, ttl)
         }
-        guardian::Input::AddAddr(port, addrs) => {
+        Update::AddAddr(port, addrs) => {
             let peer = discoverer
                 .peers
                 .entry(discoverer.peer_id.clone())
@@ -262,31 +851,31 @@ fn update_response(
                 peer.addrs.sort_unstable();
                 peer.addrs.dedup();
             }
-            make_response(discoverer, service_name)
+            make_response(discoverer, service_name, ttl)
         }
-        guardian::Input::SetTxt(key, value) => {
+        Update::SetTxt(key, value) => {
             let peer = discoverer
                 .peers
                 .entry(discoverer.peer_id.clone())
                 .or_insert_with(Peer::new);
             peer.txt.insert(key, value);
-            make_response(discoverer, service_name)
+            make_response(discoverer, service_name, ttl)
         }
-        guardian::Input::RemoveTxt(key) => {
+        Update::AddSubtype(subtype) => {
+            discoverer.subtypes.insert(subtype);
+            make_response(discoverer, service_name, ttl)
+        }
+        Update::RemoveSubtype(subtype) => {
+            discoverer.subtypes.remove(&subtype);
+            make_response(discoverer, service_name, ttl)
+        }
+        Update::RemoveTxt(key) => {
             if let Some(peer) = discoverer.peers.get_mut(&discoverer.peer_id) {
                 let _ = peer.txt.remove(&key);
-                make_response(discoverer, service_name)
+                make_response(discoverer, service_name, ttl)
             } else {
                 None
             }
         }
-        guardian::Input::AddInterface(_addr) => {
-            // Interface changes don't affect the response content
-            None
-        }
-        guardian::Input::RemoveInterface(_addr) => {
-            // Interface changes don't affect the response content
-            None
-        }
     }
 }
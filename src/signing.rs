@@ -0,0 +1,135 @@
+use crate::{names::SIGNATURE_KEY, Peer, TxtData};
+use data_encoding::{BASE32_DNSSEC, BASE64_NOPAD};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hickory_proto::rr::Name;
+use std::net::IpAddr;
+
+/// Encoded size of the signature attribute, i.e. length byte, key, `=` and unpadded base64.
+pub(crate) const SIGNATURE_ATTRIBUTE_LEN: usize =
+    1 + SIGNATURE_KEY.len() + 1 + (4 * Signature::BYTE_SIZE).div_ceil(3);
+
+/// Domain separation for the signed content.
+const CONTEXT: &[u8] = b"swarm-discovery announcement v1\0";
+
+/// How responses that do not carry a valid signature are treated.
+///
+/// A response is verified if its TXT record contains a valid signature by the key from which
+/// its peer ID is derived (see [`peer_id_from_key`]). The signature covers service name,
+/// peer ID, addresses, TXT attributes and whether the announcement is a goodbye, but not the
+/// time of sending; a recorded announcement can therefore be replayed by others as long as its
+/// content is current, but not turned into a goodbye.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Verification {
+    /// Signatures are not checked, [`Peer::is_verified`] is always false.
+    #[default]
+    Off,
+    /// Signatures are checked and the result reported by [`Peer::is_verified`].
+    Flag,
+    /// Only peers with a valid signature are passed on, all others are ignored.
+    Require,
+}
+
+/// Derive the peer ID belonging to the given public key.
+///
+/// The key is encoded in lowercase base32 (using the alphabet of [RFC 4648 section 7]),
+/// yielding a 52 character DNS label.
+///
+/// [RFC 4648 section 7]: https://datatracker.ietf.org/doc/html/rfc4648#section-7
+pub fn peer_id_from_key(key: &VerifyingKey) -> String {
+    BASE32_DNSSEC.encode(key.as_bytes())
+}
+
+/// Extract the public key from a peer ID created by [`peer_id_from_key`].
+fn key_from_peer_id(peer_id: &str) -> Option<VerifyingKey> {
+    let bytes = BASE32_DNSSEC.decode(peer_id.as_bytes()).ok()?;
+    VerifyingKey::from_bytes(&bytes.try_into().ok()?).ok()
+}
+
+/// The byte string covered by the signature.
+///
+/// A goodbye, i.e. an announcement with TTL zero, is signed as such, so that the TTLs of a
+/// recorded announcement cannot be rewritten to make other peers forget the signer.
+fn signed_content(
+    service_name: &Name,
+    peer_id: &str,
+    addrs: &[(IpAddr, u16)],
+    txt: &TxtData,
+    goodbye: bool,
+) -> Vec<u8> {
+    fn push_str(buf: &mut Vec<u8>, s: &str) {
+        push_bytes(buf, s.as_bytes());
+    }
+    fn push_bytes(buf: &mut Vec<u8>, b: &[u8]) {
+        buf.extend_from_slice(&(b.len() as u32).to_be_bytes());
+        buf.extend_from_slice(b);
+    }
+
+    let mut buf = CONTEXT.to_vec();
+    push_str(&mut buf, &service_name.to_ascii().to_ascii_lowercase());
+    push_str(&mut buf, peer_id);
+    buf.push(goodbye.into());
+    buf.extend_from_slice(&(addrs.len() as u32).to_be_bytes());
+    for (ip, port) in addrs {
+        match ip {
+            IpAddr::V4(ip) => {
+                buf.push(4);
+                buf.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                buf.push(6);
+                buf.extend_from_slice(&ip.octets());
+            }
+        }
+        buf.extend_from_slice(&port.to_be_bytes());
+    }
+    let attributes = txt.iter().filter(|(k, _)| *k != SIGNATURE_KEY);
+    buf.extend_from_slice(&(attributes.clone().count() as u32).to_be_bytes());
+    for (key, value) in attributes {
+        push_str(&mut buf, key);
+        match value {
+            None => buf.push(0),
+            Some(value) => {
+                buf.push(1);
+                push_bytes(&mut buf, value);
+            }
+        }
+    }
+    buf
+}
+
+/// Compute the value of the signature TXT attribute for an announcement of `peer`.
+///
+/// The addresses of `peer` must be sorted and deduplicated, as done by the receiver.
+pub(crate) fn sign(
+    key: &SigningKey,
+    service_name: &Name,
+    peer_id: &str,
+    peer: &Peer,
+    goodbye: bool,
+) -> String {
+    let content = signed_content(service_name, peer_id, &peer.addrs, &peer.txt, goodbye);
+    BASE64_NOPAD.encode(&key.sign(&content).to_bytes())
+}
+
+/// Check the signature of a received announcement, removing it from the TXT attributes.
+///
+/// The announcement is a goodbye if its TTL is zero.
+pub(crate) fn verify(service_name: &Name, peer_id: &str, peer: &mut Peer) -> bool {
+    let Some(Some(signature)) = peer.txt.remove(SIGNATURE_KEY) else {
+        return false;
+    };
+    let Some(key) = key_from_peer_id(peer_id) else {
+        return false;
+    };
+    let Some(signature) = BASE64_NOPAD
+        .decode(&signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+    else {
+        return false;
+    };
+    let goodbye = peer.ttl.is_zero();
+    let content = signed_content(service_name, peer_id, &peer.addrs, &peer.txt, goodbye);
+    key.verify_strict(&content, &signature).is_ok()
+}
@@ -2,7 +2,7 @@ use crate::IpClass;
 use hickory_proto::op::Message;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
     sync::{Arc, RwLock},
 };
@@ -69,6 +69,19 @@ pub enum SocketError {
         #[source]
         source: std::io::Error,
     },
+    #[error("{domain}: error setting the multicast interface")]
+    MulticastInterface {
+        domain: IP,
+        #[source]
+        source: std::io::Error,
+    },
+    #[cfg(target_os = "linux")]
+    #[error("{domain}: error restricting multicast reception to joined groups")]
+    MulticastAll {
+        domain: IP,
+        #[source]
+        source: std::io::Error,
+    },
     #[error("{domain}: error setting the multicast ttl")]
     MulticastTtl {
         domain: IP,
@@ -162,7 +175,7 @@ pub fn socket_v4(interface_addr: Option<Ipv4Addr>) -> Result<UdpSocket, SocketEr
     })
 }
 
-pub fn socket_v6() -> Result<UdpSocket, SocketError> {
+pub fn socket_v6(interface_index: Option<u32>) -> Result<UdpSocket, SocketError> {
     let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP)).map_err(|source| {
         SocketError::NewSocket {
             domain: IP::Ipv6,
@@ -195,13 +208,34 @@ pub fn socket_v6() -> Result<UdpSocket, SocketError> {
             source,
         })?;
 
-    // Join multicast on the default interface (interface index 0)
-    socket
-        .join_multicast_v6(&MDNS_IPV6, 0)
-        .map_err(|source| SocketError::JoinMulticast {
-            domain: IP::Ipv6,
-            source,
-        })?;
+    if let Some(index) = interface_index {
+        // Interface-specific sockets are only used for sending, reception for all
+        // interfaces is handled by the default socket which joins the multicast group
+        // on each of them (see Sockets::add_interface_v6).
+        socket
+            .set_multicast_if_v6(index)
+            .map_err(|source| SocketError::MulticastInterface {
+                domain: IP::Ipv6,
+                source,
+            })?;
+        // Due to IPV6_MULTICAST_ALL this socket would otherwise receive duplicates of
+        // everything the default socket receives.
+        #[cfg(target_os = "linux")]
+        socket
+            .set_multicast_all_v6(false)
+            .map_err(|source| SocketError::MulticastAll {
+                domain: IP::Ipv6,
+                source,
+            })?;
+    } else {
+        // Join multicast on the default interface (interface index 0)
+        socket
+            .join_multicast_v6(&MDNS_IPV6, 0)
+            .map_err(|source| SocketError::JoinMulticast {
+                domain: IP::Ipv6,
+                source,
+            })?;
+    }
 
     socket
         .set_nonblocking(true)
@@ -222,10 +256,22 @@ pub struct Sockets {
     v4: Option<Arc<UdpSocket>>,
     v6: Option<Arc<UdpSocket>>,
     interface_sockets_v4: Arc<RwLock<HashMap<Ipv4Addr, Arc<UdpSocket>>>>,
+    interface_sockets_v6: Arc<RwLock<HashMap<u32, InterfaceSocketV6>>>,
+}
+
+#[derive(Clone, Debug)]
+struct InterfaceSocketV6 {
+    socket: Arc<UdpSocket>,
+    /// whether the default socket joined the multicast group on this interface
+    joined: bool,
 }
 
 impl Sockets {
-    pub fn new(class: IpClass, multicast_interfaces: Vec<Ipv4Addr>) -> Result<Self, SocketError> {
+    pub fn new(
+        class: IpClass,
+        multicast_interfaces: Vec<Ipv4Addr>,
+        multicast_interfaces_v6: Vec<u32>,
+    ) -> Result<Self, SocketError> {
         // Create interface-specific sockets for multi-interface mode
         let mut interface_sockets_v4 = HashMap::new();
         for addr in &multicast_interfaces {
@@ -240,31 +286,52 @@ impl Sockets {
             }
         }
         let interface_sockets_v4 = Arc::new(RwLock::new(interface_sockets_v4));
+        let interface_sockets_v6 = Arc::new(RwLock::new(HashMap::new()));
 
-        match class {
+        let sockets = match class {
             IpClass::Auto => {
                 let socket = Self {
                     v4: socket_v4(None).ok().map(Arc::new),
-                    v6: socket_v6().ok().map(Arc::new),
-                    interface_sockets_v4: interface_sockets_v4.clone(),
+                    v6: socket_v6(None).ok().map(Arc::new),
+                    interface_sockets_v4,
+                    interface_sockets_v6,
                 };
                 if socket.v4.is_none() && socket.v6.is_none() {
                     return Err(SocketError::CannotBind);
                 }
-                Ok(socket)
+                socket
             }
-            _ => Ok(Self {
+            _ => Self {
                 v4: class
                     .has_v4()
                     .then(|| socket_v4(None).map(Arc::new))
                     .transpose()?,
                 v6: class
                     .has_v6()
-                    .then(|| socket_v6().map(Arc::new))
+                    .then(|| socket_v6(None).map(Arc::new))
                     .transpose()?,
-                interface_sockets_v4: interface_sockets_v4.clone(),
-            }),
+                interface_sockets_v4,
+                interface_sockets_v6,
+            },
+        };
+
+        // Create interface-specific sockets for multi-interface mode
+        for index in multicast_interfaces_v6 {
+            match sockets.add_interface_v6(index) {
+                Ok(()) => {
+                    tracing::debug!("Created interface-specific socket for index {}", index);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to create interface socket for index {}: {}",
+                        index,
+                        e
+                    );
+                }
+            }
         }
+
+        Ok(sockets)
     }
 
     pub fn v4(&self) -> Option<Arc<UdpSocket>> {
@@ -293,8 +360,8 @@ impl Sockets {
 
         let mut interfaces = self.interface_sockets_v4.write().unwrap();
         // need to recheck since we dropped the lock in between
-        if !interfaces.contains_key(&addr) {
-            interfaces.insert(addr, Arc::new(socket));
+        if let Entry::Vacant(entry) = interfaces.entry(addr) {
+            entry.insert(Arc::new(socket));
             tracing::info!("Added interface {} for multicast", addr);
         }
         Ok(())
@@ -318,6 +385,71 @@ impl Sockets {
         }
     }
 
+    /// Add a new IPv6 interface (by index) for multicast operations.
+    /// Returns Ok(()) if the socket was successfully created and added.
+    ///
+    /// Requires the default IPv6 socket, which joins the multicast group on this interface
+    /// and thereby receives for it.
+    pub fn add_interface_v6(&self, index: u32) -> Result<(), SocketError> {
+        let Some(v6) = &self.v6 else {
+            return Err(SocketError::CannotBind);
+        };
+
+        // Check if interface already exists
+        if self
+            .interface_sockets_v6
+            .read()
+            .unwrap()
+            .contains_key(&index)
+        {
+            return Ok(());
+        }
+
+        // Create the interface-specific socket for sending
+        let socket = socket_v6(Some(index))?;
+
+        let mut interfaces = self.interface_sockets_v6.write().unwrap();
+        // need to recheck since we dropped the lock in between
+        if let Entry::Vacant(entry) = interfaces.entry(index) {
+            // this fails if the interface is the default one, which was already joined
+            let joined = match v6.join_multicast_v6(&MDNS_IPV6, index) {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::debug!("cannot join multicast on interface {}: {}", index, e);
+                    false
+                }
+            };
+            entry.insert(InterfaceSocketV6 {
+                socket: Arc::new(socket),
+                joined,
+            });
+            tracing::info!("Added interface {} for IPv6 multicast", index);
+        }
+        Ok(())
+    }
+
+    /// Remove an IPv6 interface (by index) from multicast operations.
+    /// Returns true if the interface was found and removed.
+    pub fn remove_interface_v6(&self, index: u32) -> bool {
+        let mut interfaces = self.interface_sockets_v6.write().unwrap();
+
+        if let Some(interface) = interfaces.remove(&index) {
+            drop(interfaces);
+            // drop socket outside the lock
+            drop(interface.socket);
+            if let Some(v6) = self.v6.as_ref().filter(|_| interface.joined) {
+                if let Err(e) = v6.leave_multicast_v6(&MDNS_IPV6, index) {
+                    tracing::debug!("cannot leave multicast on interface {}: {}", index, e);
+                }
+            }
+            tracing::info!("Removed interface {} from IPv6 multicast", index);
+
+            true
+        } else {
+            false
+        }
+    }
+
     /// Get the socket for a specific IPv4 interface
     pub fn get_interface_socket_v4(&self, addr: Ipv4Addr) -> Option<Arc<UdpSocket>> {
         let interfaces = self.interface_sockets_v4.read().unwrap();
@@ -333,44 +465,50 @@ impl Sockets {
         interfaces.keys().copied().collect()
     }
 
-    pub async fn send_msg(&self, msg: &Message, mode: Mode) {
+    /// Send the message, returning the number of bytes sent summed over all sockets used.
+    pub async fn send_msg(&self, msg: &Message, mode: Mode) -> usize {
         let bytes = match msg.to_vec() {
             Ok(b) => b,
             Err(e) => {
                 tracing::warn!("error serializing mDNS: {}", e);
-                return;
+                return 0;
             }
         };
+        let mut sent = 0;
+
+        let multi_v4 = !self.interface_sockets_v4.read().unwrap().is_empty();
+        let multi_v6 = !self.interface_sockets_v6.read().unwrap().is_empty();
+        let use_v4 = matches!(mode, Mode::V4 | Mode::Any);
+        let use_v6 = matches!(mode, Mode::V6 | Mode::Any);
 
-        // Use multi-interface mode only for IPv4 when interface sockets are available
-        let use_multi_interface = !self.interface_sockets_v4.read().unwrap().is_empty()
-            && matches!(mode, Mode::V4 | Mode::Any);
+        // Use multi-interface mode when interface sockets are available for the used IP class
+        let use_multi_interface = (multi_v4 && use_v4) || (multi_v6 && use_v6);
 
         if use_multi_interface {
-            tracing::debug!(
-                "Using multi-interface mode for IPv4 sending, {} interfaces available",
-                self.interface_sockets_v4.read().unwrap().len()
-            );
-            self.send_msg_multi_interface_v4(&bytes, msg).await;
-
-            // If mode is Any, also send on IPv6 if available
-            if matches!(mode, Mode::Any) {
-                if let Some(v6) = &self.v6 {
-                    if let Err(e) = v6.send_to(&bytes, (MDNS_IPV6, MDNS_PORT)).await {
-                        tracing::warn!("error sending mDNS on IPv6: {}", e);
-                    } else {
-                        tracing::debug!(
-                            q = msg.queries.len(),
-                            an = msg.answers.len(),
-                            ad = msg.additionals.len(),
-                            "sent {} bytes on IPv6",
-                            bytes.len()
-                        );
-                    }
+            if use_v4 {
+                if multi_v4 {
+                    tracing::debug!(
+                        "Using multi-interface mode for IPv4 sending, {} interfaces available",
+                        self.interface_sockets_v4.read().unwrap().len()
+                    );
+                    sent += self.send_msg_multi_interface_v4(&bytes, msg).await;
+                } else if let Some(v4) = &self.v4 {
+                    sent += send_on(v4, IpAddr::from(MDNS_IPV4), &bytes, msg).await;
+                }
+            }
+            if use_v6 {
+                if multi_v6 {
+                    tracing::debug!(
+                        "Using multi-interface mode for IPv6 sending, {} interfaces available",
+                        self.interface_sockets_v6.read().unwrap().len()
+                    );
+                    sent += self.send_msg_multi_interface_v6(&bytes, msg).await;
+                } else if let Some(v6) = &self.v6 {
+                    sent += send_on(v6, IpAddr::from(MDNS_IPV6), &bytes, msg).await;
                 }
             }
         } else {
-            // Single interface mode or IPv6-only
+            // Single interface mode
             let (socket, addr) = match mode {
                 Mode::V4 => (self.v4.as_ref().unwrap(), IpAddr::from(MDNS_IPV4)),
                 Mode::V6 => (self.v6.as_ref().unwrap(), IpAddr::from(MDNS_IPV6)),
@@ -382,31 +520,53 @@ impl Sockets {
                     }
                 }
             };
-            if let Err(e) = socket.send_to(&bytes, (addr, MDNS_PORT)).await {
-                tracing::warn!("error sending mDNS: {}", e);
+            sent += send_on(socket, addr, &bytes, msg).await;
+        }
+        sent
+    }
+
+    async fn send_msg_multi_interface_v4(&self, bytes: &[u8], msg: &Message) -> usize {
+        let mut sent_count = 0;
+
+        // Send on all IPv4 interface-specific sockets
+        let interfaces = self.interface_sockets_v4.read().unwrap().clone();
+        for (addr, socket) in interfaces.iter() {
+            if let Err(e) = socket.send_to(bytes, (MDNS_IPV4, MDNS_PORT)).await {
+                tracing::error!("error sending mDNS on interface {}: {}", addr, e);
             } else {
                 tracing::debug!(
+                    addr = %addr,
                     q = msg.queries.len(),
                     an = msg.answers.len(),
                     ad = msg.additionals.len(),
-                    "sent {} bytes",
+                    "sent {} bytes on interface",
                     bytes.len()
                 );
+                sent_count += 1;
             }
         }
+
+        if sent_count == 0 {
+            tracing::error!("failed to send mDNS on any IPv4 interface in multi-interface mode");
+        }
+        sent_count * bytes.len()
     }
 
-    async fn send_msg_multi_interface_v4(&self, bytes: &[u8], msg: &Message) {
+    async fn send_msg_multi_interface_v6(&self, bytes: &[u8], msg: &Message) -> usize {
         let mut sent_count = 0;
 
-        // Send on all IPv4 interface-specific sockets
-        let interfaces = self.interface_sockets_v4.read().unwrap().clone();
-        for (addr, socket) in interfaces.iter() {
-            if let Err(e) = socket.send_to(bytes, (MDNS_IPV4, MDNS_PORT)).await {
-                tracing::error!("error sending mDNS on interface {}: {}", addr, e);
+        // Send on all IPv6 interface-specific sockets
+        let interfaces = self.interface_sockets_v6.read().unwrap().clone();
+        for (index, interface) in interfaces.iter() {
+            if let Err(e) = interface
+                .socket
+                .send_to(bytes, (MDNS_IPV6, MDNS_PORT))
+                .await
+            {
+                tracing::error!("error sending mDNS on interface {}: {}", index, e);
             } else {
                 tracing::debug!(
-                    addr = %addr,
+                    index = %index,
                     q = msg.queries.len(),
                     an = msg.answers.len(),
                     ad = msg.additionals.len(),
@@ -418,14 +578,96 @@ impl Sockets {
         }
 
         if sent_count == 0 {
-            tracing::error!("failed to send mDNS on any IPv4 interface in multi-interface mode");
+            tracing::error!("failed to send mDNS on any IPv6 interface in multi-interface mode");
         }
+        sent_count * bytes.len()
     }
 }
 
-#[derive(Debug)]
+async fn send_on(socket: &UdpSocket, addr: IpAddr, bytes: &[u8], msg: &Message) -> usize {
+    if let Err(e) = socket.send_to(bytes, (addr, MDNS_PORT)).await {
+        tracing::warn!("error sending mDNS: {}", e);
+        0
+    } else {
+        tracing::debug!(
+            q = msg.queries.len(),
+            an = msg.answers.len(),
+            ad = msg.additionals.len(),
+            "sent {} bytes",
+            bytes.len()
+        );
+        bytes.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Mode {
     V4,
     V6,
     Any,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn interfaces_v6_are_tracked() {
+        // the loopback interface suffices, no multicast traffic needs to be received
+        let Some(index) = if_addrs::get_if_addrs()
+            .expect("get_if_addrs")
+            .into_iter()
+            .filter(|iface| iface.is_loopback() && iface.ip().is_ipv6())
+            .find_map(|iface| iface.index)
+        else {
+            eprintln!("skipping interfaces_v6_are_tracked: no IPv6 on the loopback interface");
+            return;
+        };
+        let sockets = match Sockets::new(IpClass::V6Only, vec![], vec![index]) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                eprintln!("skipping interfaces_v6_are_tracked: {e}");
+                return;
+            }
+        };
+        let indices = |sockets: &Sockets| {
+            let interfaces = sockets.interface_sockets_v6.read().unwrap();
+            interfaces.keys().copied().collect::<Vec<_>>()
+        };
+        assert_eq!(indices(&sockets), [index]);
+        // adding an interface twice keeps its socket
+        sockets
+            .add_interface_v6(index)
+            .expect("add interface again");
+        assert_eq!(indices(&sockets), [index]);
+
+        // the interface socket sends via its interface and leaves receiving to the default socket
+        let socket = sockets.interface_sockets_v6.read().unwrap()[&index]
+            .socket
+            .clone();
+        let socket = socket2::SockRef::from(&*socket);
+        assert_eq!(socket.multicast_if_v6().unwrap(), index);
+        #[cfg(target_os = "linux")]
+        assert!(!socket.multicast_all_v6().unwrap());
+
+        assert!(sockets.remove_interface_v6(index));
+        assert!(!sockets.remove_interface_v6(index));
+        assert!(indices(&sockets).is_empty());
+    }
+
+    #[tokio::test]
+    async fn interfaces_v6_require_the_default_socket() {
+        let sockets = match Sockets::new(IpClass::V4Only, vec![], vec![]) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                eprintln!("skipping interfaces_v6_require_the_default_socket: {e}");
+                return;
+            }
+        };
+        assert!(matches!(
+            sockets.add_interface_v6(1),
+            Err(SocketError::CannotBind)
+        ));
+        assert!(!sockets.remove_interface_v6(1));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{
+    socket::Mode,
+    transport::{BoxFuture, Transport},
+};
+use hickory_proto::op::Message;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A snapshot of the activity of a discovery service, see [DropGuard::stats](crate::DropGuard::stats).
+///
+/// Counters accumulate from the start of the service, use [Stats::since] for the activity
+/// within an interval. Responses are counted per announced peer, packets of announcements
+/// split across several packets are counted once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    uptime: Duration,
+    swarm_size: u64,
+    extra_delay: Duration,
+    queries_sent: u64,
+    queries_received: u64,
+    responses_sent: u64,
+    responses_received: u64,
+    responses_suppressed: u64,
+    responses_known: u64,
+    duplicates: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl Stats {
+    /// Time since the service was spawned.
+    pub fn uptime(&self) -> Duration {
+        self.uptime
+    }
+
+    /// The current estimate S of the swarm size that drives the timing of queries and responses.
+    pub fn swarm_size(&self) -> usize {
+        self.swarm_size as usize
+    }
+
+    /// The current `extra` delay of responses, which grows after responding in large swarms.
+    pub fn extra_delay(&self) -> Duration {
+        self.extra_delay
+    }
+
+    /// Number of queries for the service sent by this peer.
+    pub fn queries_sent(&self) -> u64 {
+        self.queries_sent
+    }
+
+    /// Number of queries for the service received, including our own.
+    pub fn queries_received(&self) -> u64 {
+        self.queries_received
+    }
+
+    /// Number of responses to queries sent by this peer.
+    pub fn responses_sent(&self) -> u64 {
+        self.responses_sent
+    }
+
+    /// Number of responses received, including our own.
+    pub fn responses_received(&self) -> u64 {
+        self.responses_received
+    }
+
+    /// Number of queries not responded to because τ•φ other peers responded first.
+    pub fn responses_suppressed(&self) -> u64 {
+        self.responses_suppressed
+    }
+
+    /// Number of queries not responded to because the querier listed this peer as known answer.
+    pub fn responses_known(&self) -> u64 {
+        self.responses_known
+    }
+
+    /// Number of responses received in a query cycle beyond the first τ•φ.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Total size of the packets sent by this service, in bytes of DNS payload.
+    ///
+    /// Packets sent on several interfaces are counted once per interface.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Total size of the received packets that were relevant to this service.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Average number of bytes sent per second during [Stats::uptime].
+    pub fn bytes_sent_per_sec(&self) -> f64 {
+        per_sec(self.bytes_sent, self.uptime)
+    }
+
+    /// Average number of bytes received per second during [Stats::uptime].
+    pub fn bytes_received_per_sec(&self) -> f64 {
+        per_sec(self.bytes_received, self.uptime)
+    }
+
+    /// The activity since the `earlier` snapshot was taken, i.e. `self - earlier`.
+    ///
+    /// Call this on the later snapshot, e.g. `guard.stats().since(&before)`; with the snapshots
+    /// swapped all counts are zero. Swarm size and extra delay are those of this snapshot, the
+    /// uptime is the length of the interval.
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            uptime: self.uptime.saturating_sub(earlier.uptime),
+            swarm_size: self.swarm_size,
+            extra_delay: self.extra_delay,
+            queries_sent: self.queries_sent.saturating_sub(earlier.queries_sent),
+            queries_received: self
+                .queries_received
+                .saturating_sub(earlier.queries_received),
+            responses_sent: self.responses_sent.saturating_sub(earlier.responses_sent),
+            responses_received: self
+                .responses_received
+                .saturating_sub(earlier.responses_received),
+            responses_suppressed: self
+                .responses_suppressed
+                .saturating_sub(earlier.responses_suppressed),
+            responses_known: self.responses_known.saturating_sub(earlier.responses_known),
+            duplicates: self.duplicates.saturating_sub(earlier.duplicates),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+        }
+    }
+}
+
+fn per_sec(count: u64, interval: Duration) -> f64 {
+    if interval.is_zero() {
+        return 0.0;
+    }
+    count as f64 / interval.as_secs_f64()
+}
+
+/// A counter of [Counters], named like its metric.
+#[derive(Clone, Copy)]
+pub enum Counter {
+    QueriesSent,
+    QueriesReceived,
+    ResponsesSent,
+    ResponsesReceived,
+    ResponsesSuppressed,
+    ResponsesKnown,
+    Duplicates,
+    BytesSent,
+    BytesReceived,
+}
+
+impl Counter {
+    const ALL: usize = 9;
+
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    fn name(self) -> &'static str {
+        match self {
+            Counter::QueriesSent => "swarm_discovery_queries_sent",
+            Counter::QueriesReceived => "swarm_discovery_queries_received",
+            Counter::ResponsesSent => "swarm_discovery_responses_sent",
+            Counter::ResponsesReceived => "swarm_discovery_responses_received",
+            Counter::ResponsesSuppressed => "swarm_discovery_responses_suppressed",
+            Counter::ResponsesKnown => "swarm_discovery_responses_known",
+            Counter::Duplicates => "swarm_discovery_duplicates",
+            Counter::BytesSent => "swarm_discovery_bytes_sent",
+            Counter::BytesReceived => "swarm_discovery_bytes_received",
+        }
+    }
+}
+
+/// The statistics of a service, updated by its sender and receivers and read by its [DropGuard](crate::DropGuard).
+///
+/// With the `metrics` feature, all updates are also reported to the installed recorder,
+/// labelled with the service name.
+pub struct Counters {
+    started: Instant,
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    service: String,
+    counters: [AtomicU64; Counter::ALL],
+    swarm_size: AtomicU64,
+    /// in microseconds
+    extra_delay: AtomicU64,
+}
+
+impl Counters {
+    pub fn new(service: String) -> Self {
+        Self {
+            started: Instant::now(),
+            service,
+            counters: Default::default(),
+            swarm_size: AtomicU64::new(1),
+            extra_delay: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, counter: Counter, n: u64) {
+        self.counters[counter as usize].fetch_add(n, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(counter.name(), "service" => self.service.clone()).increment(n);
+    }
+
+    pub fn set_swarm_size(&self, size: usize) {
+        self.swarm_size.store(size as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("swarm_discovery_swarm_size", "service" => self.service.clone())
+            .set(size as f64);
+    }
+
+    pub fn set_extra_delay(&self, delay: Duration) {
+        self.extra_delay
+            .store(delay.as_micros() as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("swarm_discovery_extra_delay_seconds", "service" => self.service.clone())
+            .set(delay.as_secs_f64());
+    }
+
+    /// Count `n` responses received in a cycle of which `count` were received before.
+    ///
+    /// Those beyond the cutoff τ•φ are duplicates: they were sent because the responders
+    /// did not hear each other in time.
+    pub fn responses(&self, count: &mut u32, n: u32, cutoff: u32) {
+        let before = *count;
+        *count += n;
+        self.add(Counter::ResponsesReceived, n.into());
+        let duplicates = count.saturating_sub(cutoff.max(before));
+        if duplicates > 0 {
+            self.add(Counter::Duplicates, duplicates.into());
+        }
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        let get = |counter: Counter| self.counters[counter as usize].load(Ordering::Relaxed);
+        Stats {
+            uptime: self.started.elapsed(),
+            swarm_size: self.swarm_size.load(Ordering::Relaxed),
+            extra_delay: Duration::from_micros(self.extra_delay.load(Ordering::Relaxed)),
+            queries_sent: get(Counter::QueriesSent),
+            queries_received: get(Counter::QueriesReceived),
+            responses_sent: get(Counter::ResponsesSent),
+            responses_received: get(Counter::ResponsesReceived),
+            responses_suppressed: get(Counter::ResponsesSuppressed),
+            responses_known: get(Counter::ResponsesKnown),
+            duplicates: get(Counter::Duplicates),
+            bytes_sent: get(Counter::BytesSent),
+            bytes_received: get(Counter::BytesReceived),
+        }
+    }
+}
+
+/// A transport counting the bytes sent by one service.
+pub struct Counted {
+    pub transport: Arc<dyn Transport>,
+    pub counters: Arc<Counters>,
+}
+
+impl fmt::Debug for Counted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.transport.fmt(f)
+    }
+}
+
+impl Transport for Counted {
+    fn send_msg<'a>(&'a self, msg: &'a Message, mode: Mode) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let sent = self.transport.send_msg(msg, mode).await;
+            self.counters.add(Counter::BytesSent, sent as u64);
+            sent
+        })
+    }
+}
@@ -0,0 +1,293 @@
+//! Utilities for testing applications that use swarm discovery.
+//!
+//! The [`MemoryNetwork`] simulates a multicast network within the current process. Discovery
+//! services attached to it with [`Discoverer::with_memory_network`] do not open any sockets;
+//! all packets are delivered to every other service on the same network. Delivery relies
+//! solely on Tokio timers, so that tests can use the paused clock of Tokio's `test-util` to run
+//! large swarms deterministically in virtual time (together with [`Discoverer::with_rng_seed`]).
+//!
+//! ```rust
+//! use std::{net::{IpAddr, Ipv4Addr}, time::Duration};
+//! use swarm_discovery::{test_util::MemoryNetwork, Discoverer};
+//!
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+//! let guards = (1..=10u8)
+//!     .map(|i| {
+//!         let addr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+//!         Discoverer::new_interactive("swarm".to_owned(), format!("peer{i}"))
+//!             .with_addrs(1234, [addr])
+//!             .with_memory_network(&net, addr)
+//!             .with_rng_seed(i as u64)
+//!             .spawn(&tokio::runtime::Handle::current())
+//!             .unwrap()
+//!     })
+//!     .collect::<Vec<_>>();
+//!
+//! // this completes instantly in real time
+//! tokio::time::sleep(Duration::from_secs(10)).await;
+//! assert_eq!(guards[0].peers().await.len(), 10);
+//! assert!(net.stats().responses() > 0);
+//! # }
+//! ```
+//!
+//! [`Discoverer::with_memory_network`]: crate::Discoverer::with_memory_network
+//! [`Discoverer::with_rng_seed`]: crate::Discoverer::with_rng_seed
+
+use crate::{
+    socket::Mode,
+    transport::{BoxFuture, Receive, Transport},
+};
+use hickory_proto::op::Message;
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+/// The port reported as source of all packets received from a [`MemoryNetwork`].
+const MDNS_PORT: u16 = 5353;
+
+struct Packet {
+    deliver_at: Instant,
+    source: SocketAddr,
+    bytes: Arc<[u8]>,
+}
+
+/// A simulated multicast network connecting discovery services within one process.
+///
+/// Cloning yields another handle to the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    latency: Duration,
+    state: Mutex<State>,
+    /// the wall clock time at the creation of the network, which then advances in Tokio time
+    #[cfg_attr(not(feature = "swarm-secret"), allow(dead_code))]
+    started: (SystemTime, Instant),
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            state: Default::default(),
+            started: (SystemTime::now(), Instant::now()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    endpoints: Vec<Weak<Endpoint>>,
+    stats: NetworkStats,
+}
+
+impl MemoryNetwork {
+    /// Create a new network without latency.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the time it takes for a packet to reach its receivers.
+    ///
+    /// This must be set before attaching any discovery service.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("latency must be set before attaching discovery services")
+            .latency = latency;
+        self
+    }
+
+    /// Traffic sent on this network so far.
+    pub fn stats(&self) -> NetworkStats {
+        self.inner.state.lock().unwrap().stats
+    }
+
+    /// Attach a raw endpoint, e.g. to play the part of another mDNS implementation.
+    pub fn raw_endpoint(&self, addr: IpAddr) -> RawEndpoint {
+        RawEndpoint(self.endpoint(addr))
+    }
+
+    pub(crate) fn endpoint(&self, addr: IpAddr) -> Arc<Endpoint> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let endpoint = Arc::new(Endpoint {
+            addr,
+            network: self.inner.clone(),
+            tx,
+            rx: tokio::sync::Mutex::new(rx),
+        });
+        let mut state = self.inner.state.lock().unwrap();
+        state.endpoints.retain(|e| e.strong_count() > 0);
+        state.endpoints.push(Arc::downgrade(&endpoint));
+        endpoint
+    }
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryNetwork")
+            .field("latency", &self.inner.latency)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Counters for the traffic sent on a [`MemoryNetwork`].
+///
+/// Each packet is counted once, regardless of the number of receivers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    queries: u64,
+    responses: u64,
+    bytes: u64,
+}
+
+impl NetworkStats {
+    /// Number of query packets sent.
+    pub fn queries(&self) -> u64 {
+        self.queries
+    }
+
+    /// Number of response packets sent.
+    pub fn responses(&self) -> u64 {
+        self.responses
+    }
+
+    /// Total number of packets sent.
+    pub fn packets(&self) -> u64 {
+        self.queries + self.responses
+    }
+
+    /// Total size of all packets sent, in bytes of DNS payload.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The traffic sent since the `earlier` snapshot was taken, i.e. `self - earlier`.
+    pub fn since(&self, earlier: &NetworkStats) -> NetworkStats {
+        NetworkStats {
+            queries: self.queries.saturating_sub(earlier.queries),
+            responses: self.responses.saturating_sub(earlier.responses),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+        }
+    }
+}
+
+/// The attachment of a single discovery service to a [`MemoryNetwork`].
+pub(crate) struct Endpoint {
+    addr: IpAddr,
+    network: Arc<Inner>,
+    tx: mpsc::UnboundedSender<Packet>,
+    rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Packet>>,
+}
+
+impl fmt::Debug for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Endpoint")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Endpoint {
+    /// The wall clock of the network, advancing with the possibly paused clock of Tokio.
+    #[cfg(feature = "swarm-secret")]
+    pub(crate) fn clock(&self) -> crate::secret::Clock {
+        let (wall, at) = self.network.started;
+        crate::secret::Clock::Virtual { wall, at }
+    }
+
+    fn send_bytes(&self, bytes: Arc<[u8]>) {
+        let deliver_at = Instant::now() + self.network.latency;
+        let source = SocketAddr::new(self.addr, MDNS_PORT);
+
+        let mut state = self.network.state.lock().unwrap();
+        // the QR bit of the header flags
+        if bytes.get(2).is_some_and(|flags| flags & 0x80 != 0) {
+            state.stats.responses += 1;
+        } else {
+            state.stats.queries += 1;
+        }
+        state.stats.bytes += bytes.len() as u64;
+        // like real multicast, this includes the sender itself
+        state.endpoints.retain(|endpoint| match endpoint.upgrade() {
+            Some(endpoint) => {
+                let packet = Packet {
+                    deliver_at,
+                    source,
+                    bytes: bytes.clone(),
+                };
+                let _ = endpoint.tx.send(packet);
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+impl Transport for Endpoint {
+    fn send_msg<'a>(&'a self, msg: &'a Message, _mode: Mode) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            match msg.to_vec() {
+                Ok(bytes) => {
+                    let len = bytes.len();
+                    self.send_bytes(bytes.into());
+                    len
+                }
+                Err(e) => {
+                    tracing::warn!("error serializing message: {}", e);
+                    0
+                }
+            }
+        })
+    }
+}
+
+impl Receive for Endpoint {
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(async move {
+            let mut rx = self.rx.lock().await;
+            // the sender is owned by this endpoint, so the channel is never closed
+            let packet = rx.recv().await.expect("endpoint is alive");
+            sleep_until(packet.deliver_at).await;
+            // like UDP, truncate packets that do not fit into the buffer
+            let len = packet.bytes.len().min(buf.len());
+            buf[..len].copy_from_slice(&packet.bytes[..len]);
+            Ok((len, packet.source))
+        })
+    }
+}
+
+/// An endpoint on a [`MemoryNetwork`] that sends and receives undecoded DNS packets.
+///
+/// Like all endpoints it receives its own packets as well.
+#[derive(Debug)]
+pub struct RawEndpoint(Arc<Endpoint>);
+
+impl RawEndpoint {
+    /// Send a packet to all endpoints on the network.
+    pub fn send(&self, packet: &[u8]) {
+        self.0.send_bytes(packet.into());
+    }
+
+    /// Receive the next packet together with its source address.
+    pub async fn recv(&self) -> (Vec<u8>, SocketAddr) {
+        let mut buf = vec![0; 65535];
+        let (len, source) = self.0.recv_from(&mut buf).await.expect("endpoint is alive");
+        buf.truncate(len);
+        (buf, source)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::socket::{Mode, Sockets};
+use hickory_proto::op::Message;
+use std::{fmt::Debug, future::Future, io, net::SocketAddr, pin::Pin, sync::Arc};
+use tokio::net::UdpSocket;
+
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The sending side of the network used for discovery.
+pub(crate) trait Transport: Debug + Send + Sync + 'static {
+    /// Send the message to the multicast group, using the given IP class.
+    ///
+    /// Returns the number of bytes put on the network, zero if sending failed.
+    fn send_msg<'a>(&'a self, msg: &'a Message, mode: Mode) -> BoxFuture<'a, usize>;
+}
+
+/// The receiving side of the network used for discovery.
+pub(crate) trait Receive: Send + Sync + 'static {
+    /// Receive a single datagram, returning its length and source address.
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8])
+        -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+}
+
+impl Transport for Sockets {
+    fn send_msg<'a>(&'a self, msg: &'a Message, mode: Mode) -> BoxFuture<'a, usize> {
+        Box::pin(Sockets::send_msg(self, msg, mode))
+    }
+}
+
+impl Receive for UdpSocket {
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(UdpSocket::recv_from(self, buf))
+    }
+}
+
+/// The network a discovery service is attached to.
+#[derive(Debug, Clone)]
+pub(crate) enum Link {
+    /// Real mDNS over UDP multicast.
+    Udp(Sockets),
+    /// Simulated in-process network.
+    #[cfg(feature = "test-util")]
+    Memory(Arc<crate::test_util::Endpoint>),
+}
+
+impl Link {
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        match self {
+            Link::Udp(sockets) => Arc::new(sockets.clone()),
+            #[cfg(feature = "test-util")]
+            Link::Memory(endpoint) => endpoint.clone(),
+        }
+    }
+
+    /// Receivers to start with, together with the name of the actor to spawn for them.
+    ///
+    /// Receivers for interface-specific sockets are not included.
+    pub fn receivers(&self) -> Vec<(&'static str, Arc<dyn Receive>)> {
+        match self {
+            Link::Udp(sockets) => {
+                let mut receivers = Vec::<(&'static str, Arc<dyn Receive>)>::new();
+                if let Some(v4) = sockets.v4() {
+                    receivers.push(("receiver_v4", v4));
+                }
+                if let Some(v6) = sockets.v6() {
+                    receivers.push(("receiver_v6", v6));
+                }
+                receivers
+            }
+            #[cfg(feature = "test-util")]
+            Link::Memory(endpoint) => vec![("receiver_memory", endpoint.clone())],
+        }
+    }
+
+    /// The clock against which swarm secret timestamps are checked.
+    #[cfg(feature = "swarm-secret")]
+    pub fn clock(&self) -> crate::secret::Clock {
+        match self {
+            Link::Udp(_) => crate::secret::Clock::System,
+            #[cfg(feature = "test-util")]
+            Link::Memory(endpoint) => endpoint.clock(),
+        }
+    }
+
+    /// The UDP sockets, if this link uses the real network.
+    pub fn sockets(&self) -> Option<&Sockets> {
+        match self {
+            Link::Udp(sockets) => Some(sockets),
+            #[cfg(feature = "test-util")]
+            Link::Memory(_) => None,
+        }
+    }
+}
@@ -1,85 +1,289 @@
-use crate::{Callback, Peer};
+#[cfg(feature = "serde")]
+use crate::persist::PeerCache;
+use crate::{events::EventSender, names, Callback, DiscoveryEvent, Peer, PeerFilter};
 use acto::{AcTokioRuntime, ActoCell, ActoInput, ActoRef};
 use std::{
     collections::{BTreeMap, BTreeSet},
-    time::{Duration, Instant},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::{
+    sync::oneshot,
+    time::{sleep, Instant},
 };
-use tokio::time::sleep;
 
 pub enum Input {
     Peers(BTreeMap<String, Peer>),
+    /// Subtypes withdrawn by goodbyes, keyed by instance label.
+    Withdrawn(BTreeMap<String, BTreeSet<String>>),
     GC,
     SizeSubscription(ActoRef<usize>),
+    GetPeers(oneshot::Sender<BTreeMap<String, Peer>>),
+    GetPeer(String, oneshot::Sender<Option<Peer>>),
+    SetCadence(Duration),
+    SetResponseRate(f32),
+    /// The local peer ID is announced by another instance.
+    Conflict(String, Peer),
+    /// The local peer ID was changed from the first to the second.
+    Renamed(String, String, Peer),
+    /// Write the peer cache, replying when done.
+    #[cfg(feature = "serde")]
+    Save(oneshot::Sender<()>),
 }
 
 fn gc(me: ActoRef<Input>, interval: Duration) {
     tokio::spawn(async move {
         sleep(interval).await;
-        if !me.send(Input::GC) {
+        // retry if the mailbox is full, but not after the updater has stopped
+        if !me.send(Input::GC) && !me.is_gone() {
             gc(me, Duration::from_millis(10));
         }
     });
 }
 
-pub async fn updater(
-    mut ctx: ActoCell<Input, AcTokioRuntime>,
+/// Time after which a peer is considered gone if no response has been received from it.
+///
+/// In each cadence min(swarm size, ceil(τ•φ)) peers respond, so each peer is seen about every
+/// S/φ seconds in large swarms; three times this accounts for jitter.
+pub fn grace_period(tau: Duration, phi: f32, swarm_size: usize) -> Duration {
+    let swarm_size = swarm_size.max(1) as f32;
+    // at least one response per cycle, as for the cutoff of the sender
+    let responses = (tau.as_secs_f32() * phi).ceil().max(1.0).min(swarm_size);
+    let expected_frequency = responses / tau.as_secs_f32();
+    let frequency_per_peer = expected_frequency / swarm_size;
+    Duration::from_secs_f32(3.0 / frequency_per_peer)
+}
+
+/// Keep the current grace period in force for one more such period after changing timings.
+///
+/// Otherwise speeding up would shrink the grace period at once, expiring peers that were seen
+/// recently enough under the previous timings.
+fn keep_grace(
+    previous: Option<(Duration, Instant)>,
     tau: Duration,
     phi: f32,
+    swarm_size: usize,
+) -> (Duration, Instant) {
+    let now = Instant::now();
+    let mut grace = grace_period(tau, phi, swarm_size);
+    if let Some((previous, until)) = previous.filter(|(_, until)| now < *until) {
+        grace = grace.max(previous);
+        return (grace, until.max(now + grace));
+    }
+    (grace, now + grace)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+pub async fn updater(
+    mut ctx: ActoCell<Input, AcTokioRuntime>,
+    mut tau: Duration,
+    mut phi: f32,
     mut callback: Callback,
+    filter: PeerFilter,
+    events: EventSender,
+    liveness_events: bool,
+    peer_cache: Option<PathBuf>,
 ) {
-    let gc_interval = tau * 12345 / 9999;
+    let mut gc_interval = tau * 12345 / 9999;
     gc(ctx.me(), gc_interval);
+    // grace period before the last change of timings and the time until which it still applies
+    let mut previous_grace = None::<(Duration, Instant)>;
 
-    let mut peers = BTreeMap::new();
+    let mut notify = |event: DiscoveryEvent| {
+        callback(&event);
+        if events.receiver_count() > 0 {
+            // only fails if all streams were dropped in the meantime
+            let _ = events.send(event);
+        }
+    };
+
+    let mut peers = BTreeMap::<String, Peer>::new();
+    // peers loaded from the cache, which count into the swarm size but are only reported
+    // once they respond
+    #[allow(unused_mut)]
+    let mut tentative = BTreeMap::<String, Peer>::new();
+    #[cfg(feature = "serde")]
+    let mut peer_cache = peer_cache.map(PeerCache::new);
+    #[cfg(feature = "serde")]
+    if let Some(cache) = &peer_cache {
+        tentative = cache.load(|saved| grace_period(tau, phi, saved)).await;
+        tentative.retain(|peer_id, peer| filter(peer_id, peer));
+    }
+    // ActoRef is ordered by its actor ID, which is not affected by its interior mutability
+    #[allow(clippy::mutable_key_type)]
     let mut subscribers = BTreeSet::<ActoRef<usize>>::new();
     while let ActoInput::Message(msg) = ctx.recv().await {
         match msg {
             Input::Peers(msg) => {
-                for (id, peer) in msg {
-                    callback(&id, &peer);
-                    if peers.insert(id, peer).is_none() {
+                for (peer_id, peer) in msg {
+                    // a tentative peer is confirmed by its response and then reported below
+                    let tentative_peer = tentative.remove(&peer_id);
+                    let was_tentative = tentative_peer.is_some();
+                    // older versions announce all records with TTL zero, so this is only a
+                    // goodbye (see RFC 6762 section 10.1) from a peer known to announce a TTL
+                    let goodbye = peer.ttl.is_zero()
+                        && peers
+                            .get(&peer_id)
+                            .or(tentative_peer.as_ref())
+                            .is_some_and(|known| !known.ttl.is_zero());
+                    // a peer that is no longer of interest, e.g. after changing its TXT attributes
+                    if goodbye || !filter(&peer_id, &peer) {
+                        let known = peers.remove(&peer_id);
+                        if known.is_some() || was_tentative {
+                            for sub in &subscribers {
+                                sub.send(peers.len() + tentative.len());
+                            }
+                        }
+                        if let Some(known) = known {
+                            notify(DiscoveryEvent::Expired {
+                                peer_id,
+                                peer: known,
+                            });
+                        }
+                        continue;
+                    }
+                    // responses not listing the subtypes of a peer leave them unchanged
+                    let mut peer = peer;
+                    if let Some(known) = peers.get(&peer_id).filter(|_| peer.subtypes.is_empty()) {
+                        peer.subtypes = known.subtypes.clone();
+                    }
+                    let event = match peers.get(&peer_id) {
+                        None => Some(DiscoveryEvent::Discovered {
+                            peer_id: peer_id.clone(),
+                            peer: peer.clone(),
+                        }),
+                        Some(known) => {
+                            let addrs_changed = known.addrs != peer.addrs;
+                            let txt_changed = known.txt != peer.txt;
+                            if addrs_changed || txt_changed {
+                                Some(DiscoveryEvent::Updated {
+                                    peer_id: peer_id.clone(),
+                                    peer: peer.clone(),
+                                    addrs_changed,
+                                    txt_changed,
+                                })
+                            } else if liveness_events {
+                                Some(DiscoveryEvent::Refreshed {
+                                    peer_id: peer_id.clone(),
+                                    peer: peer.clone(),
+                                })
+                            } else {
+                                None
+                            }
+                        }
+                    };
+                    if let Some(event) = event {
+                        notify(event);
+                    }
+                    if peers.insert(peer_id, peer).is_none() && !was_tentative {
                         for sub in &subscribers {
-                            sub.send(peers.len());
+                            sub.send(peers.len() + tentative.len());
                         }
                     }
                 }
             }
+            Input::Withdrawn(mut withdrawn) => {
+                for (peer_id, peer) in peers.iter_mut().chain(tentative.iter_mut()) {
+                    if let Some(subtypes) = withdrawn.remove(&*names::label_text(peer_id)) {
+                        peer.subtypes.retain(|subtype| !subtypes.contains(subtype));
+                    }
+                }
+            }
             Input::GC => {
                 gc(ctx.me(), gc_interval);
-                if peers.is_empty() {
+                #[cfg(feature = "serde")]
+                if let Some(cache) = &mut peer_cache {
+                    cache
+                        .save_periodically(peers.iter().chain(tentative.iter()))
+                        .await;
+                }
+                if peers.is_empty() && tentative.is_empty() {
                     continue;
                 }
                 let now = Instant::now();
-                // we send min(swarmsize, ceil(tau * phi)) per cadence
-                let expected_frequency =
-                    (tau.as_secs_f32() * phi).ceil().min(peers.len() as f32) / tau.as_secs_f32();
-                let frequency_per_peer = expected_frequency / peers.len() as f32;
-                // take per-peer cadence times three to account for jitter
-                let per_peer_grace_period = Duration::from_secs_f32(3.0 / frequency_per_peer);
-                peers.retain(|peer_id, peer| {
+                let mut per_peer_grace_period =
+                    grace_period(tau, phi, peers.len() + tentative.len());
+                // peers seen before speeding up may not have been asked since then
+                match previous_grace {
+                    Some((grace, until)) if now < until => {
+                        per_peer_grace_period = per_peer_grace_period.max(grace);
+                    }
+                    _ => previous_grace = None,
+                }
+                let expired = |peer: &Peer| {
                     let age = now
                         .checked_duration_since(peer.last_seen)
                         .unwrap_or_default();
-                    let keep = age < per_peer_grace_period;
+                    // the announced TTL is an upper bound, but peers may vanish silently
+                    age >= per_peer_grace_period || (!peer.ttl.is_zero() && age >= peer.ttl)
+                };
+                // tentative peers have never been reported, so they vanish silently
+                tentative.retain(|_, peer| !expired(peer));
+                peers.retain(|peer_id, peer| {
+                    let keep = !expired(peer);
                     if !keep {
-                        callback(
-                            peer_id,
-                            &Peer {
-                                last_seen: peer.last_seen,
-                                addrs: vec![],
-                                txt: Default::default(),
-                            },
-                        );
+                        notify(DiscoveryEvent::Expired {
+                            peer_id: peer_id.clone(),
+                            peer: peer.clone(),
+                        });
                     }
                     keep
                 });
                 for sub in &subscribers {
-                    sub.send(peers.len());
+                    sub.send(peers.len() + tentative.len());
                 }
             }
             Input::SizeSubscription(sub) => {
+                // seed the estimate with the peers loaded from the cache
+                if !tentative.is_empty() {
+                    sub.send(peers.len() + tentative.len());
+                }
                 subscribers.insert(sub);
             }
+            Input::GetPeers(reply) => {
+                let _ = reply.send(peers.clone());
+            }
+            Input::GetPeer(peer_id, reply) => {
+                let _ = reply.send(peers.get(&peer_id).cloned());
+            }
+            Input::SetCadence(new_tau) => {
+                // the grace period is computed anew at each GC, the interval applies from the next one
+                previous_grace = Some(keep_grace(
+                    previous_grace,
+                    tau,
+                    phi,
+                    peers.len() + tentative.len(),
+                ));
+                tau = new_tau;
+                gc_interval = tau * 12345 / 9999;
+            }
+            Input::SetResponseRate(new_phi) => {
+                previous_grace = Some(keep_grace(
+                    previous_grace,
+                    tau,
+                    phi,
+                    peers.len() + tentative.len(),
+                ));
+                phi = new_phi;
+            }
+            Input::Conflict(peer_id, peer) => {
+                notify(DiscoveryEvent::Conflict { peer_id, peer });
+            }
+            Input::Renamed(previous, peer_id, peer) => {
+                notify(DiscoveryEvent::Renamed {
+                    peer_id,
+                    peer,
+                    previous,
+                });
+            }
+            #[cfg(feature = "serde")]
+            Input::Save(reply) => {
+                if let Some(cache) = &mut peer_cache {
+                    cache.save(peers.iter().chain(tentative.iter())).await;
+                }
+                let _ = reply.send(());
+            }
         }
     }
 }
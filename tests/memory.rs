@@ -0,0 +1,1444 @@
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{rdata, Name, RData, Record, RecordType},
+};
+use std::str::FromStr;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use swarm_discovery::{
+    test_util::{MemoryNetwork, NetworkStats, RawEndpoint},
+    Discoverer, DiscoveryEvent, DropGuard, Peer, SpawnError, Stats, SubtypeError, TimingError,
+    TxtAttributeError,
+};
+use tokio::{
+    runtime::Handle,
+    time::{sleep, Instant},
+};
+
+const TAU: Duration = Duration::from_millis(700);
+const PHI: f32 = 2.5;
+
+fn addr(i: usize) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, (i / 250) as u8, (i % 250) as u8 + 1))
+}
+
+/// Records for each peer the time at which it discovered each other peer.
+type Discoveries = Arc<Mutex<BTreeMap<usize, BTreeMap<String, Instant>>>>;
+
+/// Addresses and TXT strings of peers as seen by version 0.6.
+type OldPeers = BTreeMap<String, (Vec<(IpAddr, u16)>, Vec<String>)>;
+
+fn spawn_peer(net: &MemoryNetwork, i: usize, discoveries: &Discoveries) -> DropGuard {
+    let discoveries = discoveries.clone();
+    Discoverer::new("swarm".to_owned(), format!("peer{i}"))
+        .with_cadence(TAU)
+        .with_response_rate(PHI)
+        .with_addrs(1234, [addr(i)])
+        .with_memory_network(net, addr(i))
+        .with_rng_seed(i as u64)
+        .with_event_callback(move |event| {
+            if let DiscoveryEvent::Discovered { peer_id, .. } = event {
+                discoveries
+                    .lock()
+                    .unwrap()
+                    .entry(i)
+                    .or_default()
+                    .entry(peer_id.clone())
+                    .or_insert_with(Instant::now);
+            }
+        })
+        .spawn(&Handle::current())
+        .expect("spawn discoverer")
+}
+
+fn spawn_swarm(net: &MemoryNetwork, size: usize, discoveries: &Discoveries) -> Vec<DropGuard> {
+    (0..size).map(|i| spawn_peer(net, i, discoveries)).collect()
+}
+
+/// Run a swarm for a while and return the traffic of the last minute.
+async fn steady_state(size: usize) -> NetworkStats {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let _swarm = spawn_swarm(&net, size, &discoveries);
+
+    sleep(Duration::from_secs(30)).await;
+    let before = net.stats();
+    sleep(Duration::from_secs(60)).await;
+    net.stats().since(&before)
+}
+
+#[tokio::test(start_paused = true)]
+async fn whole_swarm_is_discovered() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let _swarm = spawn_swarm(&net, 20, &discoveries);
+
+    // with τ•φ < 2 only about two peers respond per cycle
+    sleep(Duration::from_secs(60)).await;
+
+    let discoveries = discoveries.lock().unwrap();
+    for i in 0..20 {
+        assert_eq!(discoveries[&i].len(), 20, "peer{i} has not seen all peers");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn new_peer_discovers_swarm_quickly() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let _swarm = spawn_swarm(&net, 100, &discoveries);
+    sleep(Duration::from_secs(30)).await;
+
+    let start = Instant::now();
+    let _guard = spawn_peer(&net, 100, &discoveries);
+    sleep(Duration::from_secs(10)).await;
+
+    // the README promises a worst-case latency of 1.2τ plus the response delay
+    let discoveries = discoveries.lock().unwrap();
+    let first = discoveries[&100]
+        .iter()
+        .filter(|(peer_id, _)| *peer_id != "peer100")
+        .map(|(_, time)| *time - start)
+        .min()
+        .expect("new peer discovered the swarm");
+    assert!(
+        first <= TAU * 12 / 10 + Duration::from_millis(300),
+        "{first:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_is_independent_of_swarm_size() {
+    for size in [10, 100, 200] {
+        let stats = steady_state(size).await;
+        let responses_per_sec = stats.responses() as f32 / 60.0;
+        // roughly one query per cycle of 1.1τ
+        assert!(
+            stats.queries() as f32 <= 60.0 / TAU.as_secs_f32() * 1.2,
+            "{size}: {stats:?}"
+        );
+        // response rate is a soft maximum, allow for some duplicates
+        assert!(
+            responses_per_sec <= PHI * 1.3,
+            "{size}: {stats:?}, {responses_per_sec} responses/s"
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn runs_are_deterministic() {
+    let first = steady_state(50).await;
+    let second = steady_state(50).await;
+    assert_eq!(first, second);
+}
+
+#[tokio::test(start_paused = true)]
+async fn stats_reflect_rate_limiting() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let swarm = spawn_swarm(&net, 20, &discoveries);
+    sleep(Duration::from_secs(30)).await;
+
+    let before = swarm.iter().map(DropGuard::stats).collect::<Vec<_>>();
+    let traffic_before = net.stats();
+    sleep(Duration::from_secs(60)).await;
+    let traffic = net.stats().since(&traffic_before);
+    let stats = swarm
+        .iter()
+        .zip(&before)
+        .map(|(guard, before)| guard.stats().since(before))
+        .collect::<Vec<_>>();
+    let total = |f: fn(&Stats) -> u64| stats.iter().map(f).sum::<u64>();
+
+    assert_eq!(total(Stats::queries_sent), traffic.queries(), "{traffic:?}");
+    assert_eq!(
+        total(Stats::responses_sent),
+        traffic.responses(),
+        "{traffic:?}"
+    );
+    assert_eq!(total(Stats::bytes_sent), traffic.bytes(), "{traffic:?}");
+    for stats in &stats {
+        assert_eq!(stats.uptime(), Duration::from_secs(60));
+        assert_eq!(stats.swarm_size(), 20);
+        assert!(
+            stats.queries_received().abs_diff(traffic.queries()) <= 1,
+            "{traffic:?}\n{stats:#?}"
+        );
+        assert!(
+            stats.responses_received().abs_diff(traffic.responses()) <= 1,
+            "{traffic:?}\n{stats:#?}"
+        );
+        // τ•φ responses per cycle, of which this peer sends its share
+        let cycles = stats.queries_received();
+        assert!(
+            stats.responses_suppressed() + stats.responses_known() >= cycles * 8 / 10,
+            "{stats:#?}"
+        );
+        assert!(
+            stats.duplicates() * 5 <= stats.responses_received(),
+            "{stats:#?}"
+        );
+        let rate = stats.bytes_sent_per_sec();
+        assert!((rate - stats.bytes_sent() as f64 / 60.0).abs() < 1e-6);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn timings_change_at_runtime() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let swarm = (0..20)
+        .map(|i| {
+            let discoverer = Discoverer::new("swarm".to_owned(), format!("peer{i}"))
+                .with_addrs(1234, [addr(i)])
+                .with_memory_network(&net, addr(i))
+                .with_rng_seed(i as u64);
+            let discoverer = match i {
+                0 => {
+                    let expired = expired.clone();
+                    discoverer.with_event_callback(move |event| {
+                        if let DiscoveryEvent::Expired { peer_id, .. } = event {
+                            expired.lock().unwrap().push(peer_id.clone());
+                        }
+                    })
+                }
+                _ => discoverer,
+            };
+            discoverer
+                .spawn(&Handle::current())
+                .expect("spawn discoverer")
+        })
+        .collect::<Vec<_>>();
+
+    sleep(Duration::from_secs(120)).await;
+    let before = net.stats();
+    sleep(Duration::from_secs(60)).await;
+    let background = net.stats().since(&before);
+    let known = swarm[0].peers().await.len();
+
+    // timings without a response per cycle are rejected and leave the service running
+    assert!(matches!(
+        swarm[0].set_cadence(Duration::ZERO),
+        Err(TimingError::ZeroCadence)
+    ));
+    assert!(matches!(
+        swarm[0].set_response_rate(0.0),
+        Err(TimingError::TooFewResponses { .. })
+    ));
+    assert!(matches!(
+        swarm[0].set_response_rate(f32::NAN),
+        Err(TimingError::TooFewResponses { .. })
+    ));
+    // τ=0.7s with the default φ=1.0Hz, the product is checked against the current rate
+    assert!(matches!(
+        swarm[0].set_cadence(Duration::from_millis(700)),
+        Err(TimingError::TooFewResponses { .. })
+    ));
+    for guard in &swarm {
+        guard.set_response_rate(PHI).expect("valid response rate");
+        guard.set_cadence(TAU).expect("valid cadence");
+    }
+    sleep(Duration::from_secs(10)).await;
+    let before = net.stats();
+    sleep(Duration::from_secs(60)).await;
+    let interactive = net.stats().since(&before);
+
+    // default τ=10s yields at most 6 cycles per minute, τ=0.7s ten times as many
+    assert!(background.queries() <= 6, "{background:?}");
+    assert!(interactive.queries() >= 40, "{interactive:?}");
+    // switching timings does not lose the peer table, not even temporarily
+    assert!(swarm[0].peers().await.len() >= known);
+    assert_eq!(*expired.lock().unwrap(), Vec::<String>::new());
+}
+
+#[tokio::test(start_paused = true)]
+async fn services_share_sockets() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let instance = |i: usize| {
+        let service = |name: &str| {
+            Discoverer::new_interactive(name.to_owned(), format!("{name}{i}"))
+                .with_addrs(1234, [addr(i)])
+                .with_rng_seed(i as u64)
+        };
+        Discoverer::spawn_many(
+            [
+                service("a").with_memory_network(&net, addr(i)),
+                service("b"),
+            ],
+            &Handle::current(),
+        )
+        .expect("spawn discoverers")
+    };
+    let mut first = instance(1);
+    let second = instance(2);
+
+    sleep(Duration::from_secs(5)).await;
+    let peers_a = second[0].peers().await;
+    let peers_b = second[1].peers().await;
+    assert!(peers_a.contains_key("a1") && !peers_a.contains_key("b1"));
+    assert!(peers_b.contains_key("b1") && !peers_b.contains_key("a1"));
+
+    // stopping one service keeps the other one running
+    drop(first.pop());
+    sleep(Duration::from_secs(30)).await;
+    assert!(second[0].peers().await.contains_key("a1"));
+    assert!(!second[1].peers().await.contains_key("b1"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn goodbye_expires_peer_immediately() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let mut swarm = spawn_swarm(&net, 20, &discoveries);
+    sleep(Duration::from_secs(60)).await;
+    assert!(swarm[0].peer("peer19").await.is_some());
+    assert!(swarm[0].peer("peer18").await.is_some());
+
+    // silently vanished peers would only expire after 3S/φ = 24s
+    drop(swarm.pop());
+    swarm[18].remove_all();
+    sleep(Duration::from_millis(100)).await;
+    for guard in &swarm[..18] {
+        assert!(guard.peer("peer19").await.is_none());
+        assert!(guard.peer("peer18").await.is_none());
+    }
+
+    // announced records carry RFC 6762 TTLs
+    let peer = swarm[0].peer("peer1").await.expect("peer1 is known");
+    assert_eq!(peer.ttl(), Duration::from_secs(120));
+}
+
+#[tokio::test(start_paused = true)]
+async fn peer_filter_excludes_peers() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events2 = events.clone();
+    let guards = (0..5)
+        .map(|i| {
+            let version = if i % 2 == 0 { "1" } else { "2" };
+            let discoverer = Discoverer::new("swarm".to_owned(), format!("peer{i}"))
+                .with_cadence(TAU)
+                .with_response_rate(PHI)
+                .with_addrs(1234, [addr(i)])
+                .with_txt_attributes([("version".to_owned(), Some(version.to_owned()))])
+                .unwrap()
+                .with_memory_network(&net, addr(i))
+                .with_rng_seed(i as u64);
+            let discoverer = match i {
+                0 => {
+                    let events = events2.clone();
+                    discoverer
+                        .with_peer_filter(|_, peer| {
+                            peer.txt_attribute("version") == Some(Some("1"))
+                        })
+                        .with_event_callback(move |event| {
+                            events.lock().unwrap().push(event.clone())
+                        })
+                }
+                _ => discoverer,
+            };
+            discoverer
+                .spawn(&Handle::current())
+                .expect("spawn discoverer")
+        })
+        .collect::<Vec<_>>();
+    sleep(Duration::from_secs(10)).await;
+
+    assert_eq!(
+        guards[0].peers().await.keys().collect::<Vec<_>>(),
+        ["peer0", "peer2", "peer4"]
+    );
+    assert_eq!(guards[1].peers().await.len(), 5);
+    assert!(events
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|event| ["peer0", "peer2", "peer4"].contains(&event.peer_id())));
+
+    // a peer that no longer passes the filter is expired
+    guards[2]
+        .set_txt_attribute("version".to_owned(), Some("2".to_owned()))
+        .unwrap();
+    sleep(Duration::from_secs(10)).await;
+    assert_eq!(
+        guards[0].peers().await.keys().collect::<Vec<_>>(),
+        ["peer0", "peer4"]
+    );
+    assert!(events.lock().unwrap().iter().any(|event| matches!(
+        event,
+        DiscoveryEvent::Expired { peer_id, .. } if peer_id == "peer2"
+    )));
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test(start_paused = true)]
+async fn peer_cache_survives_restarts() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let path = std::env::temp_dir().join(format!("swarm-discovery-{}.peers", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let spawn_cached = |events: Arc<Mutex<Vec<DiscoveryEvent>>>| {
+        Discoverer::new("swarm".to_owned(), "peer0".to_owned())
+            .with_cadence(TAU)
+            .with_response_rate(PHI)
+            .with_addrs(1234, [addr(0)])
+            .with_memory_network(&net, addr(0))
+            .with_rng_seed(0)
+            .with_peer_cache(&path)
+            .with_event_callback(move |event| events.lock().unwrap().push(event.clone()))
+            .spawn(&Handle::current())
+            .expect("spawn discoverer")
+    };
+    let discoveries = Discoveries::default();
+    let mut swarm = (1..6)
+        .map(|i| spawn_peer(&net, i, &discoveries))
+        .collect::<Vec<_>>();
+
+    let first = spawn_cached(Default::default());
+    sleep(Duration::from_secs(10)).await;
+    assert_eq!(first.peers().await.len(), 6);
+    // the table is written when the discovery stops
+    drop(first);
+    sleep(Duration::from_secs(1)).await;
+    assert!(path.exists());
+
+    // peer5 vanishes while peer0 is down
+    drop(swarm.pop());
+    sleep(Duration::from_secs(1)).await;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let second = spawn_cached(events.clone());
+    sleep(Duration::from_millis(10)).await;
+    // reloaded peers are tentative until they respond
+    assert!(second.peer("peer1").await.is_none());
+
+    sleep(Duration::from_secs(10)).await;
+    assert_eq!(
+        second.peers().await.keys().collect::<Vec<_>>(),
+        ["peer0", "peer1", "peer2", "peer3", "peer4"]
+    );
+    // the vanished peer was never reported, so its expiry is silent as well
+    let events = events.lock().unwrap();
+    assert!(events.iter().all(|event| event.peer_id() != "peer5"));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        DiscoveryEvent::Discovered { peer_id, .. } if peer_id == "peer1"
+    )));
+    drop(second);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(start_paused = true)]
+async fn known_answers_spread_responses_evenly() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let heard = Arc::new(Mutex::new(BTreeMap::<String, usize>::new()));
+    let heard2 = heard.clone();
+    let _observer = Discoverer::new("swarm".to_owned(), "observer".to_owned())
+        .with_cadence(TAU)
+        .with_response_rate(PHI)
+        .with_memory_network(&net, addr(0))
+        .with_rng_seed(0)
+        .with_liveness_events(true)
+        .with_event_callback(move |event| {
+            *heard2
+                .lock()
+                .unwrap()
+                .entry(event.peer_id().to_owned())
+                .or_default() += 1;
+        })
+        .spawn(&Handle::current())
+        .expect("spawn discoverer");
+    let _swarm = (1..=40)
+        .map(|i| spawn_peer(&net, i, &Discoveries::default()))
+        .collect::<Vec<_>>();
+    sleep(Duration::from_secs(30)).await;
+    heard.lock().unwrap().clear();
+    sleep(Duration::from_secs(120)).await;
+
+    // peers that were heard from recently are listed in queries and do not respond
+    let heard = heard.lock().unwrap();
+    assert_eq!(heard.len(), 40);
+    let min = heard.values().min().unwrap();
+    let max = heard.values().max().unwrap();
+    assert!(max - min <= 4, "{heard:?}");
+}
+
+type Recorded = Arc<Mutex<Vec<DiscoveryEvent>>>;
+
+fn spawn_twin(net: &MemoryNetwork, i: usize, auto_rename: bool) -> (DropGuard, Recorded) {
+    let events = Recorded::default();
+    let events2 = events.clone();
+    let guard = Discoverer::new_interactive("swarm".to_owned(), "twin".to_owned())
+        .with_addrs(1234, [addr(i)])
+        .with_memory_network(net, addr(i))
+        .with_rng_seed(i as u64)
+        .with_auto_rename(auto_rename)
+        .with_event_callback(move |event| {
+            if matches!(
+                event,
+                DiscoveryEvent::Conflict { .. } | DiscoveryEvent::Renamed { .. }
+            ) {
+                events2.lock().unwrap().push(event.clone());
+            }
+        })
+        .spawn(&Handle::current())
+        .expect("spawn discoverer");
+    (guard, events)
+}
+
+#[tokio::test(start_paused = true)]
+async fn duplicate_peer_id_is_renamed() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let observer = spawn_peer(&net, 0, &Discoveries::default());
+    let (_first, first_events) = spawn_twin(&net, 1, true);
+    sleep(Duration::from_secs(5)).await;
+
+    // the probes of the second instance are answered by the first one
+    let (_second, second_events) = spawn_twin(&net, 2, true);
+    sleep(Duration::from_secs(10)).await;
+
+    let twin = observer.peer("twin").await.expect("first twin is known");
+    assert_eq!(twin.addrs(), &[(addr(1), 1234)]);
+    let twin2 = observer.peer("twin-2").await.expect("second twin is known");
+    assert_eq!(twin2.addrs(), &[(addr(2), 1234)]);
+
+    assert!(first_events.lock().unwrap().is_empty());
+    let second_events = second_events.lock().unwrap();
+    assert!(
+        matches!(
+            &second_events[..],
+            [
+                DiscoveryEvent::Conflict { peer_id, peer },
+                DiscoveryEvent::Renamed { peer_id: new_peer_id, previous, .. },
+            ] if peer_id == "twin"
+                && peer.addrs() == [(addr(1), 1234)]
+                && new_peer_id == "twin-2"
+                && previous == "twin"
+        ),
+        "{second_events:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn peer_id_conflict_is_reported() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let (_first, first_events) = spawn_twin(&net, 1, false);
+    sleep(Duration::from_secs(5)).await;
+    let (_second, second_events) = spawn_twin(&net, 2, false);
+    sleep(Duration::from_secs(10)).await;
+
+    // each instance reports the other once, without renaming
+    let first_events = first_events.lock().unwrap();
+    assert!(
+        matches!(
+            &first_events[..],
+            [DiscoveryEvent::Conflict { peer, .. }] if peer.addrs() == [(addr(2), 1234)]
+        ),
+        "{first_events:?}"
+    );
+    let second_events = second_events.lock().unwrap();
+    assert!(
+        matches!(
+            &second_events[..],
+            [DiscoveryEvent::Conflict { peer, .. }] if peer.addrs() == [(addr(1), 1234)]
+        ),
+        "{second_events:?}"
+    );
+}
+
+/// Send a query from a raw endpoint and collect all responses seen within the next second.
+async fn ask(endpoint: &RawEndpoint, name: &str, query_type: RecordType) -> Vec<Message> {
+    let mut query = Message::new(0, MessageType::Query, OpCode::Query);
+    query.add_query(Query::query(Name::from_str(name).unwrap(), query_type));
+    endpoint.send(&query.to_vec().unwrap());
+
+    let mut responses = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while let Ok((packet, _)) = tokio::time::timeout_at(deadline, endpoint.recv()).await {
+        let msg = Message::from_vec(&packet).unwrap();
+        if msg.metadata.message_type == MessageType::Response {
+            responses.push(msg);
+        }
+    }
+    responses
+}
+
+/// Plays the part of a DNS-SD browser like `avahi-browse -r`.
+#[tokio::test(start_paused = true)]
+async fn standard_browsers_see_the_swarm() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let discoveries = Discoveries::default();
+    let _guards = (0..2)
+        .map(|i| spawn_peer(&net, i, &discoveries))
+        .collect::<Vec<_>>();
+    let browser = net.raw_endpoint(addr(100));
+    sleep(Duration::from_secs(5)).await;
+
+    let services = Name::from_str("_services._dns-sd._udp.local.").unwrap();
+    let service = Name::from_str("_swarm._udp.local.").unwrap();
+    let instance = |i: usize| {
+        Name::from_str(&format!("peer{i}"))
+            .unwrap()
+            .append_domain(&service)
+            .unwrap()
+    };
+
+    let responses = ask(&browser, "_services._dns-sd._udp.local.", RecordType::PTR).await;
+    let enumerations = responses
+        .iter()
+        .filter(|msg| msg.answers.iter().any(|record| record.name == services))
+        .collect::<Vec<_>>();
+    assert!(!enumerations.is_empty());
+    for msg in enumerations {
+        assert_eq!(msg.answers.len(), 1);
+        assert_eq!(msg.answers[0].data, RData::PTR(rdata::PTR(service.clone())));
+    }
+
+    let responses = ask(&browser, "_swarm._udp.local.", RecordType::PTR).await;
+    let mut browsed = Vec::new();
+    for msg in &responses {
+        assert!(msg.metadata.authoritative);
+        let ptrs = msg
+            .answers
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::PTR(ptr) => Some((&record.name, ptr)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ptrs.len(), 1, "{msg:?}");
+        let (name, ptr) = ptrs[0];
+        assert_eq!(*name, service);
+        // everything needed to resolve the instance comes along, see RFC 6763 section 12.1
+        let srv = msg
+            .answers
+            .iter()
+            .chain(&msg.additionals)
+            .find_map(|record| match &record.data {
+                RData::SRV(srv) if record.name == ptr.0 => Some(srv),
+                _ => None,
+            })
+            .expect("SRV record in response");
+        assert_eq!(srv.port, 1234);
+        assert!(msg
+            .additionals
+            .iter()
+            .any(|record| record.name == srv.target && matches!(record.data, RData::A(_))));
+        browsed.push(ptr.0.clone());
+    }
+    browsed.sort();
+    browsed.dedup();
+    assert_eq!(browsed, [instance(0), instance(1)]);
+
+    let responses = ask(&browser, "peer1._swarm._udp.local.", RecordType::SRV).await;
+    let resolved = responses
+        .iter()
+        .flat_map(|msg| msg.answers.iter().chain(&msg.additionals))
+        .find_map(|record| match &record.data {
+            RData::SRV(srv) if record.name == instance(1) => Some(srv.port),
+            _ => None,
+        });
+    assert_eq!(resolved, Some(1234));
+}
+
+/// Parses responses like version 0.6 did: SRV and TXT records only from the answers and
+/// addresses only from the additionals, directly under `local.`.
+fn parse_like_0_6(msg: &Message) -> OldPeers {
+    let service = Name::from_str("_swarm._udp.local.").unwrap();
+    let local = Name::from_str("local.").unwrap();
+    let mut ports = BTreeMap::<Name, Vec<(u16, String)>>::new();
+    let mut peers = OldPeers::new();
+    for record in &msg.answers {
+        if record.name.base_name() != service {
+            continue;
+        }
+        let peer_id = String::from_utf8(record.name.iter().next().unwrap().to_vec()).unwrap();
+        match &record.data {
+            RData::SRV(srv) => ports
+                .entry(srv.target.clone())
+                .or_default()
+                .push((srv.port, peer_id)),
+            RData::TXT(txt) => peers.entry(peer_id).or_default().1.extend(
+                txt.txt_data
+                    .iter()
+                    .map(|s| String::from_utf8(s.to_vec()).unwrap()),
+            ),
+            _ => {}
+        }
+    }
+    let mut ret = BTreeMap::new();
+    for record in &msg.additionals {
+        let ip: IpAddr = match &record.data {
+            RData::A(a) => a.0.into(),
+            RData::AAAA(a) => a.0.into(),
+            _ => continue,
+        };
+        if record.name.base_name() != local {
+            continue;
+        }
+        for (port, peer_id) in ports.get(&record.name).into_iter().flatten() {
+            let txt = peers.get(peer_id).map(|p| p.1.clone()).unwrap_or_default();
+            let entry = ret.entry(peer_id.clone()).or_insert((Vec::new(), txt));
+            entry.0.push((ip, *port));
+        }
+    }
+    ret
+}
+
+#[tokio::test(start_paused = true)]
+async fn version_0_6_reads_announcements() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let _guard = Discoverer::new("swarm".to_owned(), "peer1".to_owned())
+        .with_cadence(TAU)
+        .with_addrs(1234, [addr(1)])
+        .with_txt_attributes([("version".to_owned(), Some("0.7".to_owned()))])
+        .unwrap()
+        .with_memory_network(&net, addr(1))
+        .with_rng_seed(1)
+        .spawn(&Handle::current())
+        .expect("spawn discoverer");
+    let old = net.raw_endpoint(addr(2));
+    sleep(Duration::from_secs(5)).await;
+
+    // version 0.6 only queries for the PTR records of the service
+    let responses = ask(&old, "_swarm._udp.local.", RecordType::PTR).await;
+    let parsed = responses.iter().map(parse_like_0_6).collect::<Vec<_>>();
+    assert!(!parsed.is_empty());
+    for peers in parsed {
+        assert_eq!(
+            peers,
+            BTreeMap::from([(
+                "peer1".to_owned(),
+                (vec![(addr(1), 1234)], vec!["version=0.7".to_owned()])
+            )])
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn subtypes_are_browsed_separately() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let guards = (0..6)
+        .map(|i| {
+            let (peer_id, subtypes) = match i {
+                // subtypes are matched case-insensitively like all DNS names
+                2 => (format!("worker{i}"), vec!["Worker".to_owned()]),
+                0..2 => (format!("worker{i}"), vec!["worker".to_owned()]),
+                _ => (format!("observer{i}"), vec![]),
+            };
+            Discoverer::new("swarm".to_owned(), peer_id)
+                .with_cadence(TAU)
+                .with_response_rate(PHI)
+                .with_addrs(1234, [addr(i)])
+                .with_subtypes(subtypes)
+                .unwrap()
+                .with_browse_subtype("worker".to_owned())
+                .unwrap()
+                .with_memory_network(&net, addr(i))
+                .with_rng_seed(i as u64)
+                .spawn(&Handle::current())
+                .expect("spawn discoverer")
+        })
+        .collect::<Vec<_>>();
+    let browser = net.raw_endpoint(addr(100));
+    sleep(Duration::from_secs(10)).await;
+
+    // only workers respond to the queries for their subtype
+    for guard in &guards {
+        let peers = guard.peers().await;
+        assert_eq!(
+            peers.keys().collect::<Vec<_>>(),
+            ["worker0", "worker1", "worker2"]
+        );
+    }
+
+    // the whole service is still answered by everyone
+    let instances = |responses: &[Message]| {
+        let mut instances = responses
+            .iter()
+            .flat_map(|msg| &msg.answers)
+            .filter_map(|record| match &record.data {
+                RData::PTR(ptr) => Some((record.name.to_ascii(), ptr.0.to_ascii())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        instances.sort();
+        instances.dedup();
+        instances
+    };
+    let responses = ask(&browser, "_swarm._udp.local.", RecordType::PTR).await;
+    let browsed = instances(&responses);
+    assert!(browsed.contains(&(
+        "_swarm._udp.local.".to_owned(),
+        "observer5._swarm._udp.local.".to_owned()
+    )));
+    assert!(browsed.contains(&(
+        "_worker._sub._swarm._udp.local.".to_owned(),
+        "worker0._swarm._udp.local.".to_owned()
+    )));
+
+    // the observers are known from the responses above, but not listed for the subtype
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut listed = Vec::new();
+    while let Ok((packet, _)) = tokio::time::timeout_at(deadline, browser.recv()).await {
+        let msg = Message::from_vec(&packet).unwrap();
+        if msg.metadata.message_type == MessageType::Query {
+            listed.extend(instances(&[msg]).into_iter().map(|(_, instance)| instance));
+        }
+    }
+    assert!(!listed.is_empty());
+    assert!(
+        listed.iter().all(|instance| instance.starts_with("worker")),
+        "{listed:?}"
+    );
+    assert!(guards[0].peer("observer5").await.is_some());
+
+    guards[5].add_subtype("worker".to_owned()).unwrap();
+    sleep(Duration::from_secs(10)).await;
+    assert!(guards[0].peer("observer5").await.is_some());
+
+    let subtypes = |peer: Option<Peer>| {
+        let peer = peer.expect("peer is known");
+        peer.subtypes().map(ToOwned::to_owned).collect::<Vec<_>>()
+    };
+    assert_eq!(subtypes(guards[1].peer("worker0").await), ["worker"]);
+    assert_eq!(subtypes(guards[1].peer("worker2").await), ["worker"]);
+
+    // withdrawing a subtype sends a goodbye for it alone
+    guards[0].remove_subtype("worker".to_owned());
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut goodbye = None;
+    while let Ok((packet, source)) = tokio::time::timeout_at(deadline, browser.recv()).await {
+        let msg = Message::from_vec(&packet).unwrap();
+        let is_goodbye = msg.metadata.message_type == MessageType::Response
+            && msg.answers.iter().all(|record| record.ttl == 0);
+        if source.ip() == addr(0) && is_goodbye {
+            goodbye = Some(msg);
+        }
+    }
+    let goodbye = goodbye.expect("goodbye for the subtype");
+    assert_eq!(
+        instances(&[goodbye]),
+        [(
+            "_worker._sub._swarm._udp.local.".to_owned(),
+            "worker0._swarm._udp.local.".to_owned()
+        )]
+    );
+    // which browsers take as removal of the subtype, not of the peer
+    assert!(subtypes(guards[1].peer("worker0").await).is_empty());
+
+    let err = Discoverer::new("swarm".to_owned(), "peer".to_owned())
+        .with_subtypes(["x".repeat(63)])
+        .err()
+        .expect("subtype label too long");
+    assert!(matches!(err, SubtypeError::TooLong { .. }), "{err}");
+}
+
+/// Plays the part of a peer running version 0.6, which announces all records with TTL zero.
+#[tokio::test(start_paused = true)]
+async fn peers_of_older_versions_are_kept() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events2 = events.clone();
+    let observer = Discoverer::new_interactive("swarm".to_owned(), "observer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_memory_network(&net, addr(0))
+        .with_rng_seed(0)
+        .with_event_callback(move |event| events2.lock().unwrap().push(event.clone()))
+        .spawn(&Handle::current())
+        .expect("spawn observer");
+    let old = net.raw_endpoint(addr(1));
+
+    let service = Name::from_str("_swarm._udp.local.").unwrap();
+    let instance = Name::from_str("old._swarm._udp.local.").unwrap();
+    let target = Name::from_str("old-1234.local.").unwrap();
+    let mut response = Message::new(0, MessageType::Response, OpCode::Query);
+    response.metadata.authoritative = true;
+    response.add_answer(Record::from_rdata(
+        instance.clone(),
+        0,
+        RData::SRV(rdata::SRV::new(0, 0, 1234, target.clone())),
+    ));
+    response.add_answer(Record::from_rdata(
+        instance,
+        0,
+        RData::TXT(rdata::TXT::new(vec!["version=0.6".to_owned()])),
+    ));
+    response.add_additional(Record::from_rdata(
+        target,
+        0,
+        RData::A(rdata::A::from(Ipv4Addr::new(10, 0, 0, 2))),
+    ));
+    let response = response.to_vec().unwrap();
+
+    // version 0.6 answers every query for the service
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while let Ok((packet, _)) = tokio::time::timeout_at(deadline, old.recv()).await {
+        let msg = Message::from_vec(&packet).unwrap();
+        let asked = msg.metadata.message_type == MessageType::Query
+            && msg.queries.iter().any(|query| query.name() == &service);
+        if asked {
+            old.send(&response);
+        }
+    }
+
+    let peer = observer.peer("old").await.expect("old peer is known");
+    assert_eq!(peer.addrs(), &[(addr(1), 1234)]);
+    assert_eq!(peer.txt_attribute("version"), Some(Some("0.6")));
+    assert_eq!(peer.ttl(), Duration::ZERO);
+    let events = events.lock().unwrap();
+    assert!(
+        !events
+            .iter()
+            .any(|event| matches!(event, DiscoveryEvent::Expired { .. })),
+        "{events:?}"
+    );
+}
+
+/// Plays the part of another mDNS implementation, which splits its announcement across packets.
+#[tokio::test(start_paused = true)]
+async fn foreign_announcements_are_discovered() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let observer = Discoverer::new_interactive("swarm".to_owned(), "observer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_memory_network(&net, addr(0))
+        .with_rng_seed(0)
+        .spawn(&Handle::current())
+        .expect("spawn observer");
+    let device = net.raw_endpoint(addr(1));
+
+    let service = Name::from_str("_swarm._udp.local.").unwrap();
+    let instance = Name::from_labels(["Office Printer".as_bytes()])
+        .unwrap()
+        .append_domain(&service)
+        .unwrap();
+    // not directly under `local.` and shared by both ports
+    let host = Name::from_str("printer.office.local.").unwrap();
+    let announce = |records: Vec<Record>| {
+        let mut msg = Message::new(0, MessageType::Response, OpCode::Query);
+        msg.metadata.authoritative = true;
+        for mut record in records {
+            record.mdns_cache_flush = !matches!(record.data, RData::PTR(_));
+            msg.add_answer(record);
+        }
+        device.send(&msg.to_vec().unwrap());
+    };
+
+    announce(vec![
+        Record::from_rdata(
+            service.clone(),
+            4500,
+            RData::PTR(rdata::PTR(instance.clone())),
+        ),
+        Record::from_rdata(
+            instance.clone(),
+            120,
+            RData::SRV(rdata::SRV::new(0, 0, 631, host.clone())),
+        ),
+        Record::from_rdata(
+            instance.clone(),
+            120,
+            RData::SRV(rdata::SRV::new(0, 0, 9100, host.clone())),
+        ),
+        Record::from_rdata(
+            instance.clone(),
+            4500,
+            RData::TXT(rdata::TXT::new(vec!["rp=ipp".to_owned()])),
+        ),
+    ]);
+    sleep(Duration::from_secs(1)).await;
+    assert!(observer.peer("Office Printer").await.is_none());
+
+    announce(vec![Record::from_rdata(
+        host.clone(),
+        120,
+        RData::A(rdata::A::from(Ipv4Addr::new(10, 0, 0, 2))),
+    )]);
+    sleep(Duration::from_secs(1)).await;
+    let printer = observer
+        .peer("Office Printer")
+        .await
+        .expect("printer is discovered");
+    assert_eq!(printer.addrs(), &[(addr(1), 631), (addr(1), 9100)]);
+    assert_eq!(printer.txt_attribute("rp"), Some(Some("ipp")));
+
+    // addresses of hosts not known to belong to the service are not cached
+    let scanner = Name::from_labels(["Scanner".as_bytes()])
+        .unwrap()
+        .append_domain(&service)
+        .unwrap();
+    let other = Name::from_str("scanner.local.").unwrap();
+    announce(vec![Record::from_rdata(
+        other.clone(),
+        120,
+        RData::A(rdata::A::from(Ipv4Addr::new(10, 0, 0, 2))),
+    )]);
+    sleep(Duration::from_secs(1)).await;
+    announce(vec![Record::from_rdata(
+        scanner,
+        120,
+        RData::SRV(rdata::SRV::new(0, 0, 6566, other)),
+    )]);
+    sleep(Duration::from_secs(1)).await;
+    assert!(observer.peer("Scanner").await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn large_announcements_are_split() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let addrs = (0..150u16)
+        .map(|i| IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, i)))
+        .collect::<Vec<_>>();
+    let large = Discoverer::new_interactive("swarm".to_owned(), "large".to_owned())
+        .with_addrs(1234, addrs.clone())
+        // a single TXT record cannot be split, so it must fit into a packet by itself
+        .with_txt_attributes((0..10).map(|i| (format!("key{i}"), Some("x".repeat(100)))))
+        .unwrap()
+        .with_memory_network(&net, addr(1))
+        .with_rng_seed(1)
+        .spawn(&Handle::current())
+        .expect("spawn discoverer");
+    let observer = Discoverer::new_interactive("swarm".to_owned(), "observer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_memory_network(&net, addr(0))
+        .with_rng_seed(0)
+        .spawn(&Handle::current())
+        .expect("spawn observer");
+    let capture = net.raw_endpoint(addr(100));
+    let size = large.announcement_size().await;
+    assert!(size > 3 * 1000, "{size}");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut largest = 0;
+    let mut responses = 0;
+    while let Ok((packet, source)) = tokio::time::timeout_at(deadline, capture.recv()).await {
+        if source.ip() == addr(1) {
+            largest = largest.max(packet.len());
+            responses += 1;
+        }
+    }
+    assert!(largest <= 1452, "{largest}");
+    // announcing 150 addresses and 1kB of TXT takes several packets
+    assert!(responses >= 3, "{responses}");
+
+    let peer = observer
+        .peer("large")
+        .await
+        .expect("large peer is discovered");
+    let expected = addrs.iter().map(|ip| (*ip, 1234)).collect::<Vec<_>>();
+    assert_eq!(peer.addrs(), &expected[..]);
+    assert_eq!(peer.txt_attributes().count(), 10);
+}
+
+#[tokio::test(start_paused = true)]
+async fn txt_attributes_must_fit_into_a_packet() {
+    let net = MemoryNetwork::new();
+    let attributes = |n: usize| (0..n).map(|i| (format!("key{i}"), Some("x".repeat(100))));
+
+    let err = Discoverer::new_interactive("swarm".to_owned(), "peer".to_owned())
+        .with_txt_attributes(attributes(20))
+        .err()
+        .expect("20 attributes of 100 bytes do not fit");
+    assert!(matches!(err, TxtAttributeError::TooLarge { .. }), "{err}");
+
+    // a smaller packet size is checked when spawning
+    let err = Discoverer::new_interactive("swarm".to_owned(), "peer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_txt_attributes(attributes(10))
+        .unwrap()
+        .with_max_packet_size(512)
+        .with_memory_network(&net, addr(0))
+        .spawn(&Handle::current())
+        .err()
+        .expect("10 attributes of 100 bytes do not fit into 512 bytes");
+    assert!(
+        matches!(err, SpawnError::AnnouncementTooLarge { .. }),
+        "{err}"
+    );
+
+    let guard = Discoverer::new_interactive("swarm".to_owned(), "peer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_txt_attributes(attributes(10))
+        .unwrap()
+        .with_memory_network(&net, addr(0))
+        .spawn(&Handle::current())
+        .expect("spawn discoverer");
+    let size = guard.announcement_size().await;
+    assert!(size > 1000 && size <= 1452, "{size}");
+
+    let mut err = None;
+    for i in 10..20 {
+        let size = guard.announcement_size().await;
+        if let Err(e) = guard.set_txt_attribute(format!("key{i}"), Some("x".repeat(100))) {
+            // the rejected attribute is not announced
+            assert_eq!(guard.announcement_size().await, size);
+            err = Some((i, e));
+            break;
+        }
+        assert!(guard.announcement_size().await > size);
+    }
+    let (i, err) = err.expect("attributes exceed the packet size");
+    assert!(matches!(err, TxtAttributeError::TooLarge { .. }), "{err}");
+    assert_eq!(i, 13);
+
+    // removing an attribute makes room again
+    guard.remove_txt_attribute("key0".to_owned());
+    guard
+        .set_txt_attribute(format!("key{i}"), Some("x".repeat(100)))
+        .expect("attribute fits after removal");
+
+    // so does removing everything
+    guard.remove_all();
+    for i in 0..13 {
+        guard
+            .set_txt_attribute(format!("key{i}"), Some("x".repeat(100)))
+            .expect("attributes fit after removing all");
+    }
+    guard.add(1234, vec![addr(0)]);
+    let size = guard.announcement_size().await;
+    assert!(size > 13 * 100, "{size}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn binary_txt_values_are_transmitted() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let hash = vec![0x00, 0xff, b'=', 0x80, 0xc3];
+    let sender = Discoverer::new_interactive("swarm".to_owned(), "sender".to_owned())
+        .with_addrs(1234, [addr(1)])
+        .with_txt_attributes([("version".to_owned(), Some("1".to_owned()))])
+        .unwrap()
+        .with_txt_attributes_bytes([("hash".to_owned(), Some(hash.clone()))])
+        .unwrap()
+        .with_memory_network(&net, addr(1))
+        .with_rng_seed(1)
+        .spawn(&Handle::current())
+        .expect("spawn sender");
+    let observer = Discoverer::new_interactive("swarm".to_owned(), "observer".to_owned())
+        .with_addrs(1234, [addr(0)])
+        .with_memory_network(&net, addr(0))
+        .with_rng_seed(0)
+        .spawn(&Handle::current())
+        .expect("spawn observer");
+    sleep(Duration::from_secs(5)).await;
+
+    let peer = observer.peer("sender").await.expect("sender is discovered");
+    assert_eq!(peer.txt_attribute_bytes("hash"), Some(Some(&hash[..])));
+    assert_eq!(peer.txt_attribute_bytes("version"), Some(Some(&b"1"[..])));
+    // the string API only yields values that are valid UTF-8
+    assert_eq!(peer.txt_attribute("hash"), None);
+    assert_eq!(peer.txt_attribute("version"), Some(Some("1")));
+    assert_eq!(
+        peer.txt_attributes().collect::<Vec<_>>(),
+        [("version", Some("1"))]
+    );
+    assert_eq!(peer.txt_attributes_bytes().count(), 2);
+
+    sender
+        .set_txt_attribute_bytes("hash".to_owned(), Some(vec![0xfe; 32]))
+        .unwrap();
+    sleep(Duration::from_secs(5)).await;
+    let peer = observer.peer("sender").await.expect("sender is known");
+    assert_eq!(
+        peer.txt_attribute_bytes("hash"),
+        Some(Some(&[0xfe; 32][..]))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn arbitrary_peer_ids_round_trip() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let long = "12D3KooW".repeat(10);
+    let peer_ids = [
+        "with.dots.in.it".to_owned(),
+        "Grüße aus Köln".to_owned(),
+        format!("{long}-a"),
+        format!("{long}-b"),
+        "ä".repeat(40),
+        // too long for a single TXT string, the second one split within a character
+        "x".repeat(300),
+        "ö".repeat(150),
+    ];
+    let guards = peer_ids
+        .iter()
+        .enumerate()
+        .map(|(i, peer_id)| {
+            Discoverer::new_interactive("swarm".to_owned(), peer_id.clone())
+                .with_addrs(1234, [addr(i)])
+                .with_txt_attributes([("i".to_owned(), Some(i.to_string()))])
+                .unwrap()
+                .with_memory_network(&net, addr(i))
+                .with_rng_seed(i as u64)
+                .spawn(&Handle::current())
+                .expect("spawn discoverer")
+        })
+        .collect::<Vec<_>>();
+    sleep(Duration::from_secs(10)).await;
+
+    let mut expected = peer_ids.iter().collect::<Vec<_>>();
+    expected.sort();
+    for guard in &guards {
+        let peers = guard.peers().await;
+        assert_eq!(peers.keys().collect::<Vec<_>>(), expected);
+        for (i, peer_id) in peer_ids.iter().enumerate() {
+            let peer = &peers[peer_id];
+            assert_eq!(peer.addrs(), &[(addr(i), 1234)]);
+            // the full peer ID is not reported as attribute
+            assert_eq!(
+                peer.txt_attributes().collect::<Vec<_>>(),
+                [("i", Some(&*i.to_string()))]
+            );
+        }
+    }
+
+    let err = Discoverer::new_interactive("swarm".to_owned(), "peer".to_owned())
+        .with_txt_attributes([("_id".to_owned(), Some("other".to_owned()))])
+        .err()
+        .expect("_id is reserved");
+    assert!(
+        matches!(err, TxtAttributeError::ReservedKey { .. }),
+        "{err}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn known_answers_are_continued_in_further_packets() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let guards = (0..30)
+        .map(|i| {
+            Discoverer::new_interactive("swarm".to_owned(), format!("peer-with-a-long-id-{i:02}"))
+                .with_addrs(1234, [addr(i)])
+                .with_max_packet_size(512)
+                .with_memory_network(&net, addr(i))
+                .with_rng_seed(i as u64)
+                .spawn(&Handle::current())
+                .expect("spawn discoverer")
+        })
+        .collect::<Vec<_>>();
+    let capture = net.raw_endpoint(addr(100));
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut truncated = 0;
+    while let Ok((packet, _)) = tokio::time::timeout_at(deadline, capture.recv()).await {
+        assert!(packet.len() <= 512, "{}", packet.len());
+        let msg = Message::from_vec(&packet).unwrap();
+        if msg.metadata.message_type == MessageType::Query && msg.metadata.truncation {
+            truncated += 1;
+        }
+    }
+    assert!(truncated > 0);
+    for guard in &guards {
+        assert_eq!(guard.peers().await.len(), 30);
+    }
+}
+
+#[cfg(feature = "signing")]
+#[tokio::test(start_paused = true)]
+async fn unverified_announcements_are_ignored() {
+    use swarm_discovery::{peer_id_from_key, SigningKey, Verification};
+
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let spawn = |i: usize, discoverer: Discoverer| {
+        discoverer
+            .with_cadence(TAU)
+            .with_response_rate(PHI)
+            .with_addrs(1234, [addr(i)])
+            .with_memory_network(&net, addr(i))
+            .with_rng_seed(i as u64)
+            .spawn(&Handle::current())
+            .expect("spawn discoverer")
+    };
+    let key = |i: u8| SigningKey::from_bytes(&[i; 32]);
+    let signed_id = peer_id_from_key(&key(1).verifying_key());
+    let strict_id = peer_id_from_key(&key(4).verifying_key());
+
+    let _signed = spawn(
+        1,
+        Discoverer::new_signed("swarm".to_owned(), key(1))
+            .with_txt_attributes_bytes([("key".to_owned(), Some(vec![0xff, 0x00]))])
+            .unwrap(),
+    );
+    let _spoofer = spawn(2, Discoverer::new("swarm".to_owned(), signed_id.clone()));
+    let _plain = spawn(3, Discoverer::new("swarm".to_owned(), "plain".to_owned()));
+    let strict = spawn(
+        4,
+        Discoverer::new_signed("swarm".to_owned(), key(4)).with_verification(Verification::Require),
+    );
+    let lenient = spawn(
+        5,
+        Discoverer::new("swarm".to_owned(), "lenient".to_owned())
+            .with_verification(Verification::Flag),
+    );
+
+    for _ in 0..30 {
+        sleep(Duration::from_secs(1)).await;
+        let peers = strict.peers().await;
+        assert!(
+            peers
+                .keys()
+                .all(|peer_id| [&signed_id, &strict_id].contains(&peer_id)),
+            "{peers:?}"
+        );
+        assert!(peers.values().all(|peer| peer.is_verified()), "{peers:?}");
+        if let Some(peer) = peers.get(&signed_id) {
+            assert_eq!(peer.addrs(), &[(addr(1), 1234)]);
+            // binary values are signed as well, the signature itself is not reported
+            assert_eq!(
+                peer.txt_attributes_bytes().collect::<Vec<_>>(),
+                [("key", Some(&[0xff, 0x00][..]))]
+            );
+        }
+    }
+
+    assert!(strict.peer(&signed_id).await.is_some());
+
+    let plain = lenient
+        .peer("plain")
+        .await
+        .expect("unverified peer is known");
+    assert!(!plain.is_verified());
+}
+
+#[cfg(feature = "signing")]
+#[tokio::test(start_paused = true)]
+async fn forged_goodbyes_are_ignored() {
+    use swarm_discovery::{peer_id_from_key, SigningKey, Verification};
+
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let spawn = |i: usize| {
+        let key = SigningKey::from_bytes(&[i as u8; 32]);
+        Discoverer::new_signed("swarm".to_owned(), key)
+            .with_cadence(TAU)
+            .with_response_rate(PHI)
+            .with_addrs(1234, [addr(i)])
+            .with_memory_network(&net, addr(i))
+            .with_rng_seed(i as u64)
+            .with_verification(Verification::Require)
+            .spawn(&Handle::current())
+            .expect("spawn discoverer")
+    };
+    let strict = spawn(0);
+    let signed = spawn(1);
+    let signed_id = peer_id_from_key(&SigningKey::from_bytes(&[1; 32]).verifying_key());
+    let spy = net.raw_endpoint(addr(100));
+
+    // record an announcement and rewrite it into a goodbye
+    let mut forged = None;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while let Ok((packet, source)) = tokio::time::timeout_at(deadline, spy.recv()).await {
+        let mut msg = Message::from_vec(&packet).unwrap();
+        let announces = msg
+            .answers
+            .iter()
+            .any(|record| record.record_type() == RecordType::SRV);
+        if source.ip() == addr(1) && announces && forged.is_none() {
+            for record in msg.answers.iter_mut().chain(&mut msg.additionals) {
+                record.ttl = 0;
+            }
+            forged = Some(msg.to_vec().unwrap());
+        }
+    }
+    let forged = forged.expect("announcement was recorded");
+    assert!(strict.peer(&signed_id).await.is_some());
+
+    for _ in 0..10 {
+        spy.send(&forged);
+        sleep(Duration::from_millis(100)).await;
+        assert!(strict.peer(&signed_id).await.is_some());
+    }
+
+    // the signed goodbye of the peer itself is still honoured
+    drop(signed);
+    sleep(Duration::from_millis(100)).await;
+    assert!(strict.peer(&signed_id).await.is_none());
+}
+
+#[cfg(feature = "swarm-secret")]
+#[tokio::test(start_paused = true)]
+async fn swarms_are_separated_by_secret() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let spawn = |i: usize, secret: Option<&str>| {
+        let discoverer = Discoverer::new_interactive("swarm".to_owned(), format!("peer{i}"))
+            .with_addrs(1234, [addr(i)])
+            .with_memory_network(&net, addr(i))
+            .with_rng_seed(i as u64);
+        match secret {
+            Some(secret) => discoverer.with_swarm_secret(secret),
+            None => discoverer,
+        }
+        .spawn(&Handle::current())
+        .expect("spawn discoverer")
+    };
+    let red = (0..3).map(|i| spawn(i, Some("red"))).collect::<Vec<_>>();
+    let blue = (3..6).map(|i| spawn(i, Some("blue"))).collect::<Vec<_>>();
+    let plain = spawn(6, None);
+
+    sleep(Duration::from_secs(30)).await;
+
+    let ids = |range: std::ops::Range<usize>| range.map(|i| format!("peer{i}")).collect::<Vec<_>>();
+    for guard in &red {
+        assert_eq!(
+            guard.peers().await.into_keys().collect::<Vec<_>>(),
+            ids(0..3)
+        );
+    }
+    for guard in &blue {
+        assert_eq!(
+            guard.peers().await.into_keys().collect::<Vec<_>>(),
+            ids(3..6)
+        );
+    }
+    // announcements are authenticated, not encrypted
+    assert_eq!(plain.peers().await.len(), 7);
+}
+
+#[cfg(feature = "swarm-secret")]
+#[tokio::test(start_paused = true)]
+async fn replayed_packets_are_rejected() {
+    let net = MemoryNetwork::new().with_latency(Duration::from_millis(1));
+    let spawn = |i: usize| {
+        Discoverer::new_interactive("swarm".to_owned(), format!("peer{i}"))
+            .with_addrs(1234, [addr(i)])
+            .with_memory_network(&net, addr(i))
+            .with_rng_seed(i as u64)
+            .with_swarm_secret("red")
+            .spawn(&Handle::current())
+            .expect("spawn discoverer")
+    };
+    let observer = spawn(0);
+    let departed = spawn(1);
+    let spy = net.raw_endpoint(addr(100));
+
+    // record an announcement of the peer that is about to leave
+    let mut recorded = None;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while let Ok((packet, source)) = tokio::time::timeout_at(deadline, spy.recv()).await {
+        let msg = Message::from_vec(&packet).unwrap();
+        let announces = msg
+            .answers
+            .iter()
+            .any(|record| record.record_type() == RecordType::SRV);
+        if source.ip() == addr(1) && announces {
+            recorded.get_or_insert(packet);
+        }
+    }
+    let recorded = recorded.expect("announcement was recorded");
+    assert!(observer.peer("peer1").await.is_some());
+
+    drop(departed);
+    sleep(Duration::from_secs(1)).await;
+    assert!(observer.peer("peer1").await.is_none());
+
+    // the recorded packet is authentic and accepted as long as it is recent
+    spy.send(&recorded);
+    sleep(Duration::from_secs(1)).await;
+    assert!(observer.peer("peer1").await.is_some());
+
+    sleep(Duration::from_secs(60)).await;
+    assert!(observer.peer("peer1").await.is_none());
+    spy.send(&recorded);
+    sleep(Duration::from_secs(1)).await;
+    assert!(observer.peer("peer1").await.is_none());
+}